@@ -1,6 +1,7 @@
 use criterion::{criterion_group, criterion_main, Criterion};
+use dify::cli::{Background, ColorSpace, DiffStyle, Layout};
 use dify::diff;
-use image::{io::Reader as ImageReader, RgbaImage};
+use image::{ImageReader, RgbaImage};
 
 fn get_image(path: &str) -> RgbaImage {
     ImageReader::open(path)
@@ -19,56 +20,50 @@ fn criterion_benchmark(c: &mut Criterion) {
         output_image_base: None,
         do_not_check_dimensions: true,
         detect_anti_aliased_pixels: false,
+        anti_aliasing: Default::default(),
         blend_factor_of_unchanged_pixels: None,
         block_out_areas: None,
+        background: Background::White,
+        style: DiffStyle::Binary,
+        diff_color: diff::RED_PIXEL,
+        diff_color_alt: None,
+        anti_aliased_color: diff::YELLOW_PIXEL,
+        block_out_color: None,
+        layout: Layout::Diff,
+        flicker: None,
+        mask_output: None,
+        crop_to_diff: None,
+        print_metrics: false,
+        anti_aliased_budget: None,
+        text_tolerance: None,
+        shift_tolerance: None,
+        ignore_colors: Vec::new(),
+        luminance_only: false,
+        linear: false,
+        ignore_orientation: false,
+        metric: None,
+        color_space: ColorSpace::Srgb,
     };
 
     c.bench_function("1000 × 667 pixels", |b| {
         let left_image = get_image("./benches/fixtures/tiger.jpg");
         let right_image = get_image("./benches/fixtures/tiger-2.jpg");
 
-        b.iter(|| {
-            diff::get_results(
-                &left_image,
-                &right_image,
-                default_run_params.threshold,
-                default_run_params.do_not_check_dimensions,
-                default_run_params.blend_factor_of_unchanged_pixels,
-                &default_run_params.output_image_base,
-            )
-        })
+        b.iter(|| diff::get_results(&left_image, &right_image, &default_run_params))
     });
 
     c.bench_function("8400 × 4725 pixels", |b| {
         let left_image = get_image("./benches/fixtures/water-4k.png");
         let right_image = get_image("./benches/fixtures/water-4k-2.png");
 
-        b.iter(|| {
-            diff::get_results(
-                &left_image,
-                &right_image,
-                default_run_params.threshold,
-                default_run_params.do_not_check_dimensions,
-                default_run_params.blend_factor_of_unchanged_pixels,
-                &default_run_params.output_image_base,
-            )
-        })
+        b.iter(|| diff::get_results(&left_image, &right_image, &default_run_params))
     });
 
     c.bench_function("3446 × 10728 pixels", |b| {
         let left_image = get_image("./benches/fixtures/www.cypress.io.png");
         let right_image = get_image("./benches/fixtures/www.cypress.io-2.png");
 
-        b.iter(|| {
-            diff::get_results(
-                &left_image,
-                &right_image,
-                default_run_params.threshold,
-                default_run_params.do_not_check_dimensions,
-                default_run_params.blend_factor_of_unchanged_pixels,
-                &default_run_params.output_image_base,
-            )
-        })
+        b.iter(|| diff::get_results(&left_image, &right_image, &default_run_params))
     });
 }
 
@@ -0,0 +1,413 @@
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use image::{imageops, metadata::Orientation, DynamicImage, ImageDecoder, ImageReader, RgbaImage};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+// NeuQuant sampling factor used when quantizing frames into the GIF palette, 1 is the slowest
+// and best, 30 the fastest
+const GIF_QUANTIZATION_SPEED: i32 = 10;
+
+fn pad(image: &RgbaImage, width: u32, height: u32) -> RgbaImage {
+    if image.dimensions() == (width, height) {
+        return image.clone();
+    }
+
+    let mut canvas = RgbaImage::new(width, height);
+    imageops::replace(&mut canvas, image, 0, 0);
+    canvas
+}
+
+fn write_gif(file: File, frames: &[(RgbaImage, u16)], width: u16, height: u16) -> Result<()> {
+    let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &[])?;
+    encoder.set_repeat(gif::Repeat::Infinite)?;
+
+    for (image, delay) in frames {
+        let mut pixels = image.clone().into_raw();
+        let mut frame =
+            gif::Frame::from_rgba_speed(width, height, &mut pixels, GIF_QUANTIZATION_SPEED);
+        // GIF delays are in units of 10 ms
+        frame.delay = delay / 10;
+        encoder.write_frame(&frame)?;
+    }
+
+    Ok(())
+}
+
+fn write_apng(file: File, frames: &[(RgbaImage, u16)], width: u32, height: u32) -> Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_animated(frames.len() as u32, 0)?;
+
+    let mut writer = encoder.write_header()?;
+
+    for (image, delay) in frames {
+        writer.set_frame_delay(*delay, 1000)?;
+        writer.write_image_data(image.as_raw())?;
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+// writes the frames as an endlessly looping animation, the format (GIF or APNG) is picked by the
+// extension of the path; each frame comes with its delay in milliseconds and frames of different
+// dimensions are padded with transparent pixels to the largest one
+pub fn write(path: &str, frames: &[(&RgbaImage, u16)]) -> Result<()> {
+    let width = frames
+        .iter()
+        .map(|(image, _)| image.width())
+        .max()
+        .unwrap_or(0);
+    let height = frames
+        .iter()
+        .map(|(image, _)| image.height())
+        .max()
+        .unwrap_or(0);
+    let frames: Vec<(RgbaImage, u16)> = frames
+        .iter()
+        .map(|(image, delay)| (pad(image, width, height), *delay))
+        .collect();
+
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    match extension.as_deref() {
+        Some("gif") => {
+            let (width, height) = (u16::try_from(width)?, u16::try_from(height)?);
+            write_gif(File::create(path)?, &frames, width, height)
+        }
+        Some("png") | Some("apng") => write_apng(File::create(path)?, &frames, width, height),
+        _ => Err(anyhow!(
+            "unsupported animation format, possible extensions: gif, png, apng"
+        )),
+    }
+}
+
+pub struct Frame {
+    pub image: RgbaImage,
+    // in milliseconds, 0 for still images
+    pub delay: u16,
+    // how the frame is to be rotated and flipped for display, as the image or the page says
+    pub orientation: Orientation,
+}
+
+// the frames of an image, along with the ICC profile the decoder found in it
+pub struct Decoded {
+    pub frames: Vec<Frame>,
+    pub profile: Option<Vec<u8>>,
+}
+
+// failures to read the profile and the orientation are left to the decoding
+fn profile(decoder: &mut impl ImageDecoder) -> Option<Vec<u8>> {
+    decoder.icc_profile().ok().flatten()
+}
+
+fn orientation(decoder: &mut impl ImageDecoder) -> Orientation {
+    decoder.orientation().unwrap_or(Orientation::NoTransforms)
+}
+
+fn decode_still(mut decoder: impl ImageDecoder) -> Result<Decoded> {
+    let profile = profile(&mut decoder);
+    let orientation = orientation(&mut decoder);
+
+    Ok(Decoded {
+        frames: vec![Frame {
+            image: DynamicImage::from_decoder(decoder)?.to_rgba8(),
+            delay: 0,
+            orientation,
+        }],
+        profile,
+    })
+}
+
+// the frames of an animation, which all take the orientation of the container
+#[cfg(feature = "animation")]
+fn collect(frames: image::Frames, orientation: Orientation) -> Result<Vec<Frame>> {
+    frames
+        .map(|frame| {
+            let frame = frame?;
+            let (numerator, denominator) = frame.delay().numer_denom_ms();
+            let delay = u16::try_from(numerator / denominator.max(1)).unwrap_or(u16::MAX);
+
+            Ok(Frame {
+                image: frame.into_buffer(),
+                delay,
+                orientation,
+            })
+        })
+        .collect()
+}
+
+#[cfg(feature = "animation")]
+fn decode_tiff_page<R: std::io::Read + std::io::Seek>(
+    decoder: &mut tiff::decoder::Decoder<R>,
+) -> Result<RgbaImage> {
+    use image::{GrayAlphaImage, GrayImage, RgbImage};
+    use tiff::{decoder::DecodingResult, ColorType};
+
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    let samples = match decoder.read_image()? {
+        DecodingResult::U8(samples) => samples,
+        DecodingResult::U16(samples) => samples.iter().map(|sample| (sample >> 8) as u8).collect(),
+        _ => return Err(anyhow!("unsupported sample format of TIFF page")),
+    };
+
+    let image = match color_type {
+        ColorType::Gray(_) => GrayImage::from_raw(width, height, samples).map(DynamicImage::from),
+        ColorType::GrayA(_) => {
+            GrayAlphaImage::from_raw(width, height, samples).map(DynamicImage::from)
+        }
+        ColorType::RGB(_) => RgbImage::from_raw(width, height, samples).map(DynamicImage::from),
+        ColorType::RGBA(_) => RgbaImage::from_raw(width, height, samples).map(DynamicImage::from),
+        color_type => {
+            return Err(anyhow!(
+                "unsupported color type of TIFF page: {:?}",
+                color_type
+            ))
+        }
+    };
+
+    image
+        .map(|image| image.to_rgba8())
+        .ok_or_else(|| anyhow!("unsupported bit depth of TIFF page"))
+}
+
+// every page of a multi-page TIFF, which the decoder of the image crate stops reading after the
+// first one of
+#[cfg(feature = "animation")]
+fn decode_tiff(mut reader: BufReader<File>) -> Result<Decoded> {
+    use image::codecs::tiff::TiffDecoder;
+    use std::io::{Seek, SeekFrom};
+    use tiff::tags::Tag;
+
+    // the tag of the embedded ICC profile, which the tiff crate has no name for
+    const TAG_ICC_PROFILE: u16 = 34675;
+
+    let mut decoder = tiff::decoder::Decoder::new(&mut reader)?;
+    if !decoder.more_images() {
+        reader.seek(SeekFrom::Start(0))?;
+        return decode_still(TiffDecoder::new(reader)?);
+    }
+
+    let profile = decoder.get_tag_u8_vec(Tag::Unknown(TAG_ICC_PROFILE)).ok();
+    let mut frames = Vec::new();
+    loop {
+        // every page has its own orientation
+        let orientation = decoder
+            .get_tag_u32(Tag::Orientation)
+            .ok()
+            .and_then(|orientation| u8::try_from(orientation).ok())
+            .and_then(Orientation::from_exif)
+            .unwrap_or(Orientation::NoTransforms);
+        frames.push(Frame {
+            image: decode_tiff_page(&mut decoder)?,
+            delay: 0,
+            orientation,
+        });
+
+        if !decoder.more_images() {
+            return Ok(Decoded { frames, profile });
+        }
+        decoder.next_image()?;
+    }
+}
+
+#[cfg(feature = "animation")]
+fn decode(reader: ImageReader<BufReader<File>>) -> Result<Decoded> {
+    use image::codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder};
+    use image::{AnimationDecoder, ImageFormat};
+
+    match reader.format() {
+        Some(ImageFormat::Gif) => {
+            let mut decoder = GifDecoder::new(reader.into_inner())?;
+            let (profile, orientation) = (profile(&mut decoder), orientation(&mut decoder));
+            Ok(Decoded {
+                frames: collect(decoder.into_frames(), orientation)?,
+                profile,
+            })
+        }
+        Some(ImageFormat::Png) => {
+            let mut decoder = PngDecoder::new(reader.into_inner())?;
+            if decoder.is_apng()? {
+                let (profile, orientation) = (profile(&mut decoder), orientation(&mut decoder));
+                Ok(Decoded {
+                    frames: collect(decoder.apng()?.into_frames(), orientation)?,
+                    profile,
+                })
+            } else {
+                decode_still(decoder)
+            }
+        }
+        Some(ImageFormat::WebP) => {
+            let mut decoder = WebPDecoder::new(reader.into_inner())?;
+            if decoder.has_animation() {
+                let (profile, orientation) = (profile(&mut decoder), orientation(&mut decoder));
+                Ok(Decoded {
+                    frames: collect(decoder.into_frames(), orientation)?,
+                    profile,
+                })
+            } else {
+                decode_still(decoder)
+            }
+        }
+        Some(ImageFormat::Tiff) => decode_tiff(reader.into_inner()),
+        _ => decode_still(reader.into_decoder()?),
+    }
+}
+
+#[cfg(not(feature = "animation"))]
+fn decode(reader: ImageReader<BufReader<File>>) -> Result<Decoded> {
+    decode_still(reader.into_decoder()?)
+}
+
+// reads every frame of animated GIF, PNG and WebP images and every page of TIFF images, other
+// images are read as a single frame
+pub fn read(path: &str, which: &str) -> Result<Decoded> {
+    let reader = ImageReader::open(path)
+        .with_context(|| format!("failed to open {} image \"{}\"", which, path.magenta()).red())?
+        .with_guessed_format()?;
+
+    decode(reader)
+        .and_then(|decoded| match decoded.frames.is_empty() {
+            true => Err(anyhow!("no frames")),
+            false => Ok(decoded),
+        })
+        .with_context(|| format!("failed to decode {} image \"{}\"", which, path.magenta()).red())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::gif::GifDecoder, codecs::png::PngDecoder, AnimationDecoder, Rgba};
+    use std::io::BufReader;
+    use std::time::Duration;
+
+    fn frames() -> (RgbaImage, RgbaImage) {
+        (
+            RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255])),
+            RgbaImage::from_pixel(1, 1, Rgba([0, 0, 255, 255])),
+        )
+    }
+
+    #[test]
+    fn test_write_gif() {
+        let (first, second) = frames();
+        let path = std::env::temp_dir().join("dify-test_write_gif.gif");
+        let path = path.to_str().unwrap();
+        write(path, &[(&first, 500), (&second, 200)]).unwrap();
+
+        let decoder = GifDecoder::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        let decoded = decoder.into_frames().collect_frames().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(2, decoded.len());
+        assert_eq!(
+            Duration::from_millis(500),
+            Duration::from(decoded[0].delay())
+        );
+        assert_eq!((2, 2), decoded[1].buffer().dimensions());
+        assert_eq!(&Rgba([0, 0, 255, 255]), decoded[1].buffer().get_pixel(0, 0));
+    }
+
+    #[test]
+    fn test_write_apng() {
+        let (first, second) = frames();
+        let path = std::env::temp_dir().join("dify-test_write_apng.png");
+        let path = path.to_str().unwrap();
+        write(path, &[(&first, 500), (&second, 200)]).unwrap();
+
+        let decoder = PngDecoder::new(BufReader::new(File::open(path).unwrap())).unwrap();
+        let decoded = decoder
+            .apng()
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(2, decoded.len());
+        assert_eq!(
+            Duration::from_millis(200),
+            Duration::from(decoded[1].delay())
+        );
+        assert_eq!(&Rgba([255, 0, 0, 255]), decoded[0].buffer().get_pixel(1, 1));
+        assert_eq!(&Rgba([0, 0, 0, 0]), decoded[1].buffer().get_pixel(1, 1));
+    }
+
+    #[test]
+    #[cfg(feature = "animation")]
+    fn test_read_animated() {
+        let (first, second) = frames();
+
+        for extension in ["gif", "png"].iter() {
+            let path = std::env::temp_dir().join(format!("dify-test_read_animated.{extension}"));
+            let path = path.to_str().unwrap();
+            write(path, &[(&first, 500), (&second, 200)]).unwrap();
+
+            let frames = read(path, "left").unwrap().frames;
+            std::fs::remove_file(path).unwrap();
+
+            assert_eq!(2, frames.len());
+            assert_eq!(vec![500, 200], vec![frames[0].delay, frames[1].delay]);
+            assert_eq!((2, 2), frames[1].image.dimensions());
+            assert_eq!(&Rgba([0, 0, 255, 255]), frames[1].image.get_pixel(0, 0));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "animation")]
+    fn test_read_tiff_pages() {
+        use tiff::encoder::{colortype, TiffEncoder};
+        use tiff::tags::Tag;
+
+        let (first, second) = frames();
+        let path = std::env::temp_dir().join("dify-test_read_tiff_pages.tiff");
+        let mut encoder = TiffEncoder::new(File::create(&path).unwrap()).unwrap();
+        // the second page is rotated by 90 degrees clockwise when displayed
+        for (image, orientation) in [(&first, 1u16), (&second, 6u16)].iter() {
+            let mut page = encoder
+                .new_image::<colortype::RGBA8>(image.width(), image.height())
+                .unwrap();
+            page.encoder()
+                .write_tag(Tag::Orientation, *orientation)
+                .unwrap();
+            page.write_data(image.as_raw()).unwrap();
+        }
+
+        let frames = read(path.to_str().unwrap(), "left").unwrap().frames;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(2, frames.len());
+        assert_eq!(first, frames[0].image);
+        assert_eq!(second, frames[1].image);
+        assert_eq!(Orientation::NoTransforms, frames[0].orientation);
+        assert_eq!(Orientation::Rotate90, frames[1].orientation);
+    }
+
+    #[test]
+    fn test_read_still() {
+        let (first, _) = frames();
+        let path = std::env::temp_dir().join("dify-test_read_still.png");
+        first.save(&path).unwrap();
+
+        let frames = read(path.to_str().unwrap(), "left").unwrap().frames;
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, frames.len());
+        assert_eq!(0, frames[0].delay);
+        assert_eq!(first, frames[0].image);
+    }
+
+    #[test]
+    fn test_write_unsupported() {
+        let (first, _) = frames();
+        assert!(write("flicker.jpg", &[(&first, 500)]).is_err());
+    }
+}
@@ -1,8 +1,12 @@
+use crate::config::{self, Config};
+use crate::diff::{RED_PIXEL, YELLOW_PIXEL};
 use anyhow::{anyhow, Context, Result};
 use colored::*;
 use getopts::{Matches, Options};
+use image::Rgba;
 use std::collections::HashSet;
 use std::env;
+use std::path::Path;
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 const SHORT_NAME_HELP: &str = "h";
@@ -14,13 +18,155 @@ const SHORT_NAME_THRESHOLD: &str = "t";
 const SHORT_NAME_DETECT_ANTI_ALIASED_PIXELS: &str = "d";
 const SHORT_NAME_BLEND_FACTOR_OF_UNCHENGED_PIXELS: &str = "a";
 const SHORT_NAME_BLOCK_OUT_AREA: &str = "b";
+const LONG_NAME_BACKGROUND: &str = "background";
+const LONG_NAME_STYLE: &str = "style";
+const LONG_NAME_DIFF_COLOR: &str = "diff-color";
+const LONG_NAME_DIFF_COLOR_ALT: &str = "diff-color-alt";
+const LONG_NAME_ANTI_ALIASED_COLOR: &str = "aa-color";
+const LONG_NAME_BLOCK_OUT_COLOR: &str = "blockout-color";
+const LONG_NAME_LAYOUT: &str = "layout";
+const LONG_NAME_LABELS: &str = "labels";
+const LONG_NAME_FLICKER: &str = "flicker";
+const LONG_NAME_FLICKER_DIFF: &str = "flicker-diff";
+const LONG_NAME_FLICKER_DELAY: &str = "flicker-delay";
+const LONG_NAME_MASK_OUTPUT: &str = "mask-output";
+const LONG_NAME_CROP_TO_DIFF: &str = "crop-to-diff";
+const LONG_NAME_CROP_PADDING: &str = "crop-padding";
+const LONG_NAME_CONFIG: &str = "config";
+const LONG_NAME_BLOCK_OUT_MASK: &str = "block-out-mask";
+const LONG_NAME_WATCH: &str = "watch";
+const LONG_NAME_PORT: &str = "port";
+const LONG_NAME_DIR: &str = "dir";
+const LONG_NAME_SEQUENCE: &str = "sequence";
+const LONG_NAME_METRICS: &str = "metrics";
+const LONG_NAME_AA_RADIUS: &str = "aa-radius";
+const LONG_NAME_AA_SIBLINGS: &str = "aa-siblings";
+const LONG_NAME_AA_TOLERANCE: &str = "aa-tolerance";
+const LONG_NAME_AA_BUDGET: &str = "aa-budget";
+const LONG_NAME_TEXT_TOLERANCE: &str = "text-tolerance";
+const LONG_NAME_SHIFT_TOLERANCE: &str = "shift-tolerance";
+const LONG_NAME_IGNORE_COLOR: &str = "ignore-color";
+const LONG_NAME_LUMINANCE_ONLY: &str = "luminance-only";
+const LONG_NAME_METRIC: &str = "metric";
+const LONG_NAME_COLOR_SPACE: &str = "color-space";
+const LONG_NAME_LINEAR: &str = "linear";
+const LONG_NAME_IGNORE_ORIENTATION: &str = "ignore-orientation";
+const SUBCOMMAND_SERVE: &str = "serve";
+const SUBCOMMAND_GIT_DIFFTOOL: &str = "git-difftool";
+const SUBCOMMAND_TEXTCONV: &str = "textconv";
+const SUBCOMMAND_STATS: &str = "stats";
 const DEFAULT_PATH_OF_DIFF_IMAGE: &str = "diff.png";
+const CHECKERBOARD_SQUARE_SIZE: u32 = 8;
 
+pub enum Subcommand {
+    Serve,
+    GitDifftool,
+    Textconv,
+    Stats,
+}
+
+// the color space that images are converted into from their embedded ICC profiles
+#[derive(Clone)]
+pub enum ColorSpace {
+    Srgb,
+    DisplayP3,
+    // the values of the pixels are compared as they are
+    Unmanaged,
+}
+
+#[derive(Clone)]
+pub enum Metric {
+    Histogram,
+}
+
+#[derive(Clone)]
 pub enum OutputImageBase {
     LeftImage,
     RightImage,
 }
 
+#[derive(Clone)]
+pub enum Background {
+    White,
+    Black,
+    Checkerboard,
+}
+
+impl Background {
+    pub fn at(&self, x: u32, y: u32) -> f32 {
+        match self {
+            Background::White => 255.0,
+            Background::Black => 0.0,
+            Background::Checkerboard => {
+                if (x / CHECKERBOARD_SQUARE_SIZE + y / CHECKERBOARD_SQUARE_SIZE).is_multiple_of(2) {
+                    255.0
+                } else {
+                    204.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum DiffStyle {
+    Binary,
+    Heatmap,
+    Luminance,
+}
+
+#[derive(Clone)]
+pub enum Layout {
+    Diff,
+    SideBySide { labels: bool },
+}
+
+// tuning of the anti-aliasing detection, the defaults match pixelmatch
+#[derive(Clone)]
+pub struct AntiAliasing {
+    // how far around a pixel its neighbours are looked at
+    pub radius: u32,
+    // how many similar neighbours make a pixel part of a flat area instead of an edge
+    pub siblings: u32,
+    // the luminance delta, from 0 to 255, within which neighbours count as similar
+    pub tolerance: f32,
+}
+
+impl Default for AntiAliasing {
+    fn default() -> Self {
+        Self {
+            radius: 1,
+            siblings: 3,
+            tolerance: 0.0,
+        }
+    }
+}
+
+// a color whose pixels are blocked out, e.g. the color of a blinking caret
+#[derive(Clone, Debug, PartialEq)]
+pub struct IgnoreColor {
+    pub color: Rgba<u8>,
+    // the largest difference, from 0 to 255, of any channel within which a pixel matches
+    pub tolerance: u8,
+}
+
+impl IgnoreColor {
+    pub fn matches(&self, pixel: &Rgba<u8>) -> bool {
+        self.color
+            .0
+            .iter()
+            .zip(pixel.0.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= self.tolerance)
+    }
+}
+
+#[derive(Clone)]
+pub struct Flicker {
+    pub path: String,
+    pub delay: u16,
+    pub with_diff: bool,
+}
+
 pub struct Cli {
     program: String,
     matches: Matches,
@@ -36,6 +182,13 @@ impl Cli {
         options.optflag(SHORT_NAME_HELP, "help", "Print this help menu.");
         options.optflag(SHORT_NAME_VERSION, "version", "Print the version.");
 
+        options.optopt(
+            "",
+            LONG_NAME_CONFIG,
+            &format!("Config file whose settings are used for the options not given on the command line. (default: {} in the working directory or its ancestors)", config::FILE_NAME),
+            "FILE",
+        );
+
         options.optmulti(
             SHORT_NAME_BLOCK_OUT_AREA,
             "block-out",
@@ -43,6 +196,45 @@ impl Cli {
             "x,y,w,h",
         );
 
+        options.optmulti(
+            "",
+            LONG_NAME_BLOCK_OUT_MASK,
+            "Image whose pixels that are neither black nor transparent are blocked out. Can be repeated multiple times.",
+            "FILE",
+        );
+
+        options.optmulti(
+            "",
+            LONG_NAME_IGNORE_COLOR,
+            "Color whose pixels are blocked out when they differ and either image has it, with an optional tolerance from 0 to 255 for each channel, after a comma in hex notation or a slash in any notation. Can be repeated multiple times.",
+            "#RRGGBB[,TOLERANCE]",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_COLOR_SPACE,
+            "Color space that images are converted into from their embedded ICC profiles before comparing, images without a profile are taken to be sRGB. With none, the values of the pixels are compared as they are. (default: srgb)",
+            "{srgb, display-p3, none}",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_IGNORE_ORIENTATION,
+            "Compares images as they are stored, instead of rotated and flipped by their EXIF orientation.",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_LINEAR,
+            "Decodes the gamma of sRGB before blending semi-transparent pixels and computing differences, so that they are measured in linear light.",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_LUMINANCE_ONLY,
+            "Compares the luminance of pixels only, so that differences in color alone are ignored. The threshold applies to the luminance on the same scale.",
+        );
+
         options.optflag(
             SHORT_NAME_DONT_CHECK_DIMENSIONS,
             "ignore-dimensions",
@@ -56,6 +248,94 @@ impl Cli {
             "NUM"
         );
 
+        options.optopt(
+            "",
+            LONG_NAME_BACKGROUND,
+            "Background that semi-transparent pixels are blended onto before comparing. (default: white)",
+            "{white, black, checkerboard}",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_STYLE,
+            "Style of the diff output. The heatmap colors every changed pixel by the magnitude of its difference, on the same scale as the threshold. The luminance style draws it in gray, darker or lighter as the right image is. (default: binary)",
+            "{binary, heatmap, luminance}",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_DIFF_COLOR,
+            "Color of different pixels. (default: #ff0000)",
+            "COLOR",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_DIFF_COLOR_ALT,
+            "Color of different pixels where the right image is darker than the left one. (default: same as --diff-color)",
+            "COLOR",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_ANTI_ALIASED_COLOR,
+            "Color of anti-aliased pixels. (default: #ffff00)",
+            "COLOR",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_BLOCK_OUT_COLOR,
+            "Color of block-out areas, they are not drawn unless it is given.",
+            "COLOR",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_LAYOUT,
+            "Layout of the output image. side-by-side puts left, right and diff into one image, stacked vertically for wide images. (default: diff)",
+            "{diff, side-by-side}",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_LABELS,
+            "Labels the images of the side-by-side layout.",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_FLICKER,
+            "Also writes an animation alternating between the left and right image, GIF or APNG by extension.",
+            "OUTPUT",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_FLICKER_DIFF,
+            "Adds the diff image as the third frame of the flicker animation.",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_FLICKER_DELAY,
+            "Delay between the frames of the flicker animation in milliseconds. (default: 500)",
+            "MS",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_CROP_TO_DIFF,
+            "Crops the output images to the area of the differences. The offset of the area is printed.",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_CROP_PADDING,
+            "Padding around the area of the differences when cropping in pixels. (default: 0)",
+            "PIXELS",
+        );
+
         options.optflagopt(
             SHORT_NAME_COPY_IMAGE_AS_BASE,
             "copy-image",
@@ -69,6 +349,48 @@ impl Cli {
             "Detects anti-aliased pixels. (default: false)",
         );
 
+        options.optopt(
+            "",
+            LONG_NAME_AA_RADIUS,
+            "How far around a pixel its neighbours are looked at to detect anti-aliasing. (default: 1)",
+            "PIXELS",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_AA_SIBLINGS,
+            "How many similar neighbours within the radius make a pixel part of a flat area rather than an anti-aliased edge, fewer are required at the borders of the image. (default: 3)",
+            "COUNT",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_AA_TOLERANCE,
+            "The luminance delta, from 0 to 255, within which neighbours count as similar to a pixel, higher values detect anti-aliasing more aggressively. (default: 0)",
+            "DELTA",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_AA_BUDGET,
+            "Fails when more pixels than this differ by anti-aliasing only, implies --detect-anti-aliased.",
+            "PIXELS",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_TEXT_TOLERANCE,
+            "Treats differences confined to a band of this width around the edges in the left image, such as fringes of glyphs rendered with different font hinting, as anti-aliased.",
+            "PIXELS",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_SHIFT_TOLERANCE,
+            "Considers a pixel matching when any pixel of the right image within this radius is within the threshold of the left pixel, and any pixel of the left image within it is within the threshold of the right pixel, so that content moved by a few pixels passes.",
+            "PIXELS",
+        );
+
         options.optopt(
             SHORT_NAME_OUTPUT_IMAGE_PATH,
             "output",
@@ -76,6 +398,52 @@ impl Cli {
             "OUTPUT",
         );
 
+        options.optopt(
+            "",
+            LONG_NAME_MASK_OUTPUT,
+            "Also writes a grayscale mask of the changes, white for different, gray for anti-aliased and black for unchanged pixels, PNG only.",
+            "OUTPUT",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_WATCH,
+            "Compares the images again whenever one of them changes, until interrupted.",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_METRICS,
+            "Prints the MSE and PSNR of the red, green and blue channels and the RMSE of each channel, computed like ffmpeg on a scale of 0 to 255.",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_METRIC,
+            "Additionally prints a metric of the whole images. The histogram compares the distributions of the red, green and blue channels by chi-square and Bhattacharyya distance, along with the dominant colors.",
+            "{histogram}",
+        );
+
+        options.optflag(
+            "",
+            LONG_NAME_SEQUENCE,
+            "Compares image sequences, LEFT and RIGHT are patterns of the frame paths like frame_%04d.png. A diff sequence is written when the output is a pattern as well.",
+        );
+
+        options.optopt(
+            "",
+            LONG_NAME_PORT,
+            "Port of the review server, which listens on localhost only. (default: 8080)",
+            "PORT",
+        );
+
+        // accepted for readability only, the review server always compares directories
+        options.optflag(
+            "",
+            LONG_NAME_DIR,
+            "Marks BASELINE_DIR and ACTUAL_DIR of the review server as directories, which they always are.",
+        );
+
         options.optopt(
             SHORT_NAME_THRESHOLD,
             "threshold",
@@ -83,18 +451,63 @@ impl Cli {
             "NUM",
         );
 
-        match options.parse(&args[1..]) {
-            Ok(matches) => Ok(Self {
-                program: args[0].clone(),
-                matches,
-                options,
-            }),
-            Err(f) => Err(anyhow!(f)),
-        }
+        let matches = options.parse(&args[1..]).map_err(|f| anyhow!(f))?;
+
+        // the help and the version are printed even when the config file is broken
+        let config =
+            if matches.opt_present(SHORT_NAME_HELP) || matches.opt_present(SHORT_NAME_VERSION) {
+                None
+            } else {
+                match matches.opt_str(LONG_NAME_CONFIG) {
+                    Some(path) => Some(Config::load(Path::new(&path))?),
+                    None => Config::discover()?,
+                }
+            };
+
+        let matches = match config {
+            Some(config) => {
+                let files: Vec<&str> = matches.free.iter().map(String::as_str).collect();
+                let config_args =
+                    config.to_args(&files, |name| matches.opts_present(&[name.to_owned()]))?;
+
+                // settings of the config file go first so that they cannot end up after "--"
+                let args: Vec<String> = config_args
+                    .into_iter()
+                    .chain(args[1..].iter().cloned())
+                    .collect();
+
+                options.parse(&args).map_err(|f| {
+                    anyhow!(format!(
+                        "{} in config file \"{}\"",
+                        f,
+                        config.path().display().to_string().magenta()
+                    )
+                    .red())
+                })?
+            }
+            None => matches,
+        };
+
+        Ok(Self {
+            program: args[0].clone(),
+            matches,
+            options,
+        })
     }
 
     pub fn print_help(&self) {
-        let brief = format!("Usage: {} [options] <LEFT> <RIGHT>", self.program);
+        let brief = format!(
+            "Usage: {0} [options] <LEFT> <RIGHT>\n       \
+             {0} {1} [options] [--dir] <BASELINE_DIR> <ACTUAL_DIR>\n       \
+             {0} {2} [options] <LOCAL> <REMOTE> [<MERGED>]\n       \
+             {0} {3} <FILE>\n       \
+             {0} {4} <LEFT> <RIGHT>",
+            self.program,
+            SUBCOMMAND_SERVE,
+            SUBCOMMAND_GIT_DIFFTOOL,
+            SUBCOMMAND_TEXTCONV,
+            SUBCOMMAND_STATS
+        );
         print!("{}", self.options.usage(&brief));
     }
 
@@ -129,6 +542,225 @@ impl Cli {
         }
     }
 
+    pub fn get_background(&self) -> Result<Background> {
+        match self.matches.opt_str(LONG_NAME_BACKGROUND) {
+            Some(value) => match &value.to_lowercase()[..] {
+                "white" => Ok(Background::White),
+                "black" => Ok(Background::Black),
+                "checkerboard" => Ok(Background::Checkerboard),
+                unsupported => Err(anyhow!(format!(
+                    "--background \"{}\" is not supported, possible values: white, black, checkerboard",
+                    unsupported.magenta()
+                )
+                .red())),
+            },
+            None => Ok(Background::White),
+        }
+    }
+
+    pub fn get_diff_style(&self) -> Result<DiffStyle> {
+        match self.matches.opt_str(LONG_NAME_STYLE) {
+            Some(value) => match &value.to_lowercase()[..] {
+                "binary" => Ok(DiffStyle::Binary),
+                "heatmap" => Ok(DiffStyle::Heatmap),
+                "luminance" => Ok(DiffStyle::Luminance),
+                unsupported => Err(anyhow!(format!(
+                    "--style \"{}\" is not supported, possible values: binary, heatmap, luminance",
+                    unsupported.magenta()
+                )
+                .red())),
+            },
+            None => Ok(DiffStyle::Binary),
+        }
+    }
+
+    pub fn get_layout(&self) -> Result<Layout> {
+        match self.matches.opt_str(LONG_NAME_LAYOUT) {
+            Some(value) => match &value.to_lowercase()[..] {
+                "diff" => Ok(Layout::Diff),
+                "side-by-side" => Ok(Layout::SideBySide {
+                    labels: self.matches.opt_present(LONG_NAME_LABELS),
+                }),
+                unsupported => Err(anyhow!(format!(
+                    "--layout \"{}\" is not supported, possible values: diff, side-by-side",
+                    unsupported.magenta()
+                )
+                .red())),
+            },
+            None => Ok(Layout::Diff),
+        }
+    }
+
+    fn get_number<T>(&self, name: &str, default: T) -> Result<T>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.get_optional_number(name)
+            .map(|number| number.unwrap_or(default))
+    }
+
+    fn get_optional_number<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: std::str::FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.matches
+            .opt_str(name)
+            .map(|s| {
+                s.parse::<T>().with_context(|| {
+                    format!(
+                        "the value of {} is invalid",
+                        format!("--{name} {s}").magenta()
+                    )
+                    .red()
+                })
+            })
+            .transpose()
+    }
+
+    pub fn get_anti_aliasing(&self) -> Result<AntiAliasing> {
+        let default = AntiAliasing::default();
+        let radius = self.get_number(LONG_NAME_AA_RADIUS, default.radius)?;
+        let siblings = self.get_number(LONG_NAME_AA_SIBLINGS, default.siblings)?;
+        let tolerance = self.get_number(LONG_NAME_AA_TOLERANCE, default.tolerance)?;
+
+        if radius == 0 || siblings == 0 {
+            return Err(anyhow!(format!(
+                "the values of {} and {} should be at least 1",
+                format!("--{LONG_NAME_AA_RADIUS}").magenta(),
+                format!("--{LONG_NAME_AA_SIBLINGS}").magenta()
+            )
+            .red()));
+        }
+
+        Ok(AntiAliasing {
+            radius,
+            siblings,
+            tolerance,
+        })
+    }
+
+    pub fn get_flicker(&self) -> Result<Option<Flicker>> {
+        let path = match self.matches.opt_str(LONG_NAME_FLICKER) {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let delay = self.get_number(LONG_NAME_FLICKER_DELAY, 500)?;
+
+        Ok(Some(Flicker {
+            path,
+            delay,
+            with_diff: self.matches.opt_present(LONG_NAME_FLICKER_DIFF),
+        }))
+    }
+
+    fn get_color(&self, name: &str) -> Result<Option<Rgba<u8>>> {
+        self.matches
+            .opt_str(name)
+            .map(|s| {
+                parse_color(&s).with_context(|| {
+                    format!(
+                        "the value of {} is invalid, expected #RRGGBB, #RRGGBBAA or R,G,B[,A]",
+                        format!("--{name} {s}").magenta()
+                    )
+                    .red()
+                })
+            })
+            .transpose()
+    }
+
+    pub fn get_diff_color(&self) -> Result<Rgba<u8>> {
+        self.get_color(LONG_NAME_DIFF_COLOR)
+            .map(|color| color.unwrap_or(RED_PIXEL))
+    }
+
+    pub fn get_diff_color_alt(&self) -> Result<Option<Rgba<u8>>> {
+        self.get_color(LONG_NAME_DIFF_COLOR_ALT)
+    }
+
+    pub fn get_anti_aliased_color(&self) -> Result<Rgba<u8>> {
+        self.get_color(LONG_NAME_ANTI_ALIASED_COLOR)
+            .map(|color| color.unwrap_or(YELLOW_PIXEL))
+    }
+
+    pub fn get_block_out_color(&self) -> Result<Option<Rgba<u8>>> {
+        self.get_color(LONG_NAME_BLOCK_OUT_COLOR)
+    }
+
+    pub fn get_ignore_colors(&self) -> Result<Vec<IgnoreColor>> {
+        self.matches
+            .opt_strs(LONG_NAME_IGNORE_COLOR)
+            .iter()
+            .map(|s| {
+                parse_ignore_color(s).with_context(|| {
+                    format!(
+                        "the value of {} is invalid, expected #RRGGBB[,TOLERANCE] or R,G,B[/TOLERANCE]",
+                        format!("--{LONG_NAME_IGNORE_COLOR} {s}").magenta()
+                    )
+                    .red()
+                })
+            })
+            .collect()
+    }
+
+    pub fn ignore_orientation(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_IGNORE_ORIENTATION)
+    }
+
+    pub fn linear(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_LINEAR)
+    }
+
+    pub fn luminance_only(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_LUMINANCE_ONLY)
+    }
+
+    pub fn get_color_space(&self) -> Result<ColorSpace> {
+        match self.matches.opt_str(LONG_NAME_COLOR_SPACE) {
+            Some(value) => match &value.to_lowercase()[..] {
+                "srgb" => Ok(ColorSpace::Srgb),
+                "display-p3" => Ok(ColorSpace::DisplayP3),
+                "none" => Ok(ColorSpace::Unmanaged),
+                unsupported => Err(anyhow!(format!(
+                    "--{} \"{}\" is not supported, possible values: srgb, display-p3, none",
+                    LONG_NAME_COLOR_SPACE,
+                    unsupported.magenta()
+                )
+                .red())),
+            },
+            None => Ok(ColorSpace::Srgb),
+        }
+    }
+
+    pub fn get_metric(&self) -> Result<Option<Metric>> {
+        match self.matches.opt_str(LONG_NAME_METRIC) {
+            Some(value) => match &value.to_lowercase()[..] {
+                "histogram" => Ok(Some(Metric::Histogram)),
+                unsupported => Err(anyhow!(format!(
+                    "--{} \"{}\" is not supported, possible values: histogram",
+                    LONG_NAME_METRIC,
+                    unsupported.magenta()
+                )
+                .red())),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn print_metrics(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_METRICS)
+    }
+
+    pub fn sequence(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_SEQUENCE)
+    }
+
+    pub fn watch(&self) -> bool {
+        self.matches.opt_present(LONG_NAME_WATCH)
+    }
+
     pub fn do_not_check_dimensions(&self) -> bool {
         self.matches.opt_present(SHORT_NAME_DONT_CHECK_DIMENSIONS)
     }
@@ -136,6 +768,19 @@ impl Cli {
     pub fn detect_anti_aliased_pixels(&self) -> bool {
         self.matches
             .opt_present(SHORT_NAME_DETECT_ANTI_ALIASED_PIXELS)
+            || self.matches.opt_present(LONG_NAME_AA_BUDGET)
+    }
+
+    pub fn get_shift_tolerance(&self) -> Result<Option<u32>> {
+        self.get_optional_number(LONG_NAME_SHIFT_TOLERANCE)
+    }
+
+    pub fn get_text_tolerance(&self) -> Result<Option<u32>> {
+        self.get_optional_number(LONG_NAME_TEXT_TOLERANCE)
+    }
+
+    pub fn get_anti_aliased_budget(&self) -> Result<Option<u32>> {
+        self.get_optional_number(LONG_NAME_AA_BUDGET)
     }
 
     pub fn blend_factor_of_unchanged_pixels(&self) -> Result<Option<f32>> {
@@ -177,7 +822,33 @@ impl Cli {
     pub fn get_output_image_path(&self) -> String {
         self.matches
             .opt_str(SHORT_NAME_OUTPUT_IMAGE_PATH)
-            .unwrap_or_else(|| DEFAULT_PATH_OF_DIFF_IMAGE.to_owned())
+            .unwrap_or_else(|| match self.get_subcommand() {
+                // git runs difftools from the top of the work tree, keep the diff out of it
+                Some(Subcommand::GitDifftool) => {
+                    let name = self
+                        .get_merged_path()
+                        .and_then(|path| Path::new(path).file_stem())
+                        .and_then(|stem| stem.to_str())
+                        .unwrap_or("diff");
+                    env::temp_dir()
+                        .join(format!("dify-{name}.png"))
+                        .to_string_lossy()
+                        .into_owned()
+                }
+                _ => DEFAULT_PATH_OF_DIFF_IMAGE.to_owned(),
+            })
+    }
+
+    pub fn crop_to_diff(&self) -> Result<Option<u32>> {
+        if !self.matches.opt_present(LONG_NAME_CROP_TO_DIFF) {
+            return Ok(None);
+        }
+
+        self.get_number(LONG_NAME_CROP_PADDING, 0).map(Some)
+    }
+
+    pub fn get_mask_output_path(&self) -> Option<String> {
+        self.matches.opt_str(LONG_NAME_MASK_OUTPUT)
     }
 
     pub fn get_threshold(&self) -> Result<f32> {
@@ -194,24 +865,65 @@ impl Cli {
             })
     }
 
+    pub fn get_subcommand(&self) -> Option<Subcommand> {
+        match self.matches.free.first().map(String::as_str) {
+            Some(SUBCOMMAND_SERVE) => Some(Subcommand::Serve),
+            Some(SUBCOMMAND_GIT_DIFFTOOL) => Some(Subcommand::GitDifftool),
+            Some(SUBCOMMAND_TEXTCONV) => Some(Subcommand::Textconv),
+            Some(SUBCOMMAND_STATS) => Some(Subcommand::Stats),
+            _ => None,
+        }
+    }
+
+    // free arguments following the subcommand, if any
+    fn get_arguments(&self) -> &[String] {
+        match self.get_subcommand() {
+            Some(_) => &self.matches.free[1..],
+            None => &self.matches.free,
+        }
+    }
+
+    pub fn get_port(&self) -> Result<u16> {
+        self.matches.opt_str(LONG_NAME_PORT).map_or(Ok(8080), |s| {
+            s.parse::<u16>().with_context(|| {
+                format!(
+                    "the value of {} is invalid",
+                    format!("--{LONG_NAME_PORT} {s}").magenta()
+                )
+                .red()
+            })
+        })
+    }
+
+    // the path of the file in the work tree, which git passes as $MERGED
+    pub fn get_merged_path(&self) -> Option<&str> {
+        self.get_arguments().get(2).map(String::as_str)
+    }
+
+    pub fn get_image_path_of_textconv(&self) -> Result<&str> {
+        self.get_arguments()
+            .first()
+            .map(String::as_str)
+            .with_context(|| format!("the {} argument is missing", "FILE".magenta()).red())
+    }
+
     pub fn get_image_paths_of_left_right_diff(&self) -> Result<(&str, &str)> {
         let left_image = self
-            .matches
-            .free
+            .get_arguments()
             .first()
             .with_context(|| format!("the {} argument is missing", "LEFT".magenta()).red())?;
 
         let right_image = self
-            .matches
-            .free
+            .get_arguments()
             .get(1)
             .with_context(|| format!("the {} argument is missing", "RIGHT".magenta()).red())?;
 
         Ok((left_image, right_image))
     }
 
-    pub fn get_block_out_area(&self) -> Option<HashSet<(u32, u32)>> {
-        self.matches
+    pub fn get_block_out_area(&self) -> Result<Option<HashSet<(u32, u32)>>> {
+        let areas: Option<HashSet<(u32, u32)>> = self
+            .matches
             .opt_strs(SHORT_NAME_BLOCK_OUT_AREA)
             .iter()
             .fold(None, |acc, area| {
@@ -241,6 +953,120 @@ impl Cli {
                         Some(acc)
                     }
                 }
+            });
+
+        self.matches
+            .opt_strs(LONG_NAME_BLOCK_OUT_MASK)
+            .iter()
+            .try_fold(areas, |acc, path| {
+                let mask = image::open(path)
+                    .with_context(|| {
+                        format!("failed to open block-out mask \"{}\"", path.magenta()).red()
+                    })?
+                    .to_luma_alpha8();
+                let mut acc = acc.unwrap_or_default();
+                for (x, y, pixel) in mask.enumerate_pixels() {
+                    if pixel[0] > 0 && pixel[1] > 0 {
+                        acc.insert((x, y));
+                    }
+                }
+                Ok(Some(acc))
             })
     }
 }
+
+// accepts hex notation (#RRGGBB or #RRGGBBAA, the hash is optional) or decimal R,G,B[,A]
+fn parse_color(value: &str) -> Option<Rgba<u8>> {
+    let channels: Vec<u8> = if value.contains(',') {
+        value
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>().ok())
+            .collect::<Option<_>>()?
+    } else {
+        let hex = value.trim_start_matches('#');
+        if !hex.is_ascii() || !matches!(hex.len(), 6 | 8) {
+            return None;
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+            .collect::<Option<_>>()?
+    };
+
+    match channels[..] {
+        [r, g, b] => Some(Rgba([r, g, b, u8::MAX])),
+        [r, g, b, a] => Some(Rgba([r, g, b, a])),
+        _ => None,
+    }
+}
+
+// a color in any notation of `parse_color`, followed by a tolerance after a slash, e.g.
+// 0,120,215/16, or after a single comma in hex notation, e.g. #ffffff,16
+fn parse_ignore_color(value: &str) -> Option<IgnoreColor> {
+    let (color, tolerance) = match value.rsplit_once('/') {
+        Some(pair) => pair,
+        None if value.split(',').count() == 2 => value.split_once(',')?,
+        None => (value, "0"),
+    };
+
+    Some(IgnoreColor {
+        color: parse_color(color)?,
+        tolerance: tolerance.trim().parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_color, parse_ignore_color, IgnoreColor};
+    use image::Rgba;
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(Some(Rgba([255, 0, 0, 255])), parse_color("#ff0000"));
+        assert_eq!(Some(Rgba([255, 0, 0, 255])), parse_color("FF0000"));
+        assert_eq!(Some(Rgba([0, 128, 255, 64])), parse_color("#0080ff40"));
+        assert_eq!(Some(Rgba([0, 128, 255, 255])), parse_color("0,128,255"));
+        assert_eq!(
+            Some(Rgba([0, 128, 255, 64])),
+            parse_color("0, 128, 255, 64")
+        );
+        assert_eq!(None, parse_color("#ff00"));
+        assert_eq!(None, parse_color("#ff000"));
+        assert_eq!(None, parse_color("#gg0000"));
+        assert_eq!(None, parse_color("0,128,256"));
+        assert_eq!(None, parse_color("red"));
+        assert_eq!(None, parse_color(""));
+    }
+
+    #[test]
+    fn test_parse_ignore_color() {
+        let caret = |tolerance| {
+            Some(IgnoreColor {
+                color: Rgba([0, 120, 215, 255]),
+                tolerance,
+            })
+        };
+
+        assert_eq!(caret(0), parse_ignore_color("#0078d7"));
+        assert_eq!(caret(16), parse_ignore_color("#0078d7,16"));
+        assert_eq!(caret(0), parse_ignore_color("0,120,215"));
+        assert_eq!(caret(16), parse_ignore_color("0,120,215/16"));
+        assert_eq!(caret(16), parse_ignore_color("#0078d7/16"));
+        assert_eq!(None, parse_ignore_color("0,120,215/"));
+        assert_eq!(None, parse_ignore_color("#0078d7,256"));
+        assert_eq!(None, parse_ignore_color("#0078d7,"));
+        assert_eq!(None, parse_ignore_color("blue,16"));
+    }
+
+    #[test]
+    fn test_ignore_color_matches() {
+        let ignore = IgnoreColor {
+            color: Rgba([100, 100, 100, 255]),
+            tolerance: 10,
+        };
+
+        assert!(ignore.matches(&Rgba([110, 90, 100, 255])));
+        assert!(!ignore.matches(&Rgba([111, 100, 100, 255])));
+        assert!(!ignore.matches(&Rgba([100, 100, 100, 200])));
+    }
+}
@@ -0,0 +1,300 @@
+use super::{cli, yiq};
+use colored::*;
+use image::{Rgba, RgbaImage};
+use qcms::{CIE_xyY, CIE_xyYTRIPLE, DataType, Intent, Profile, Transform};
+
+const D65: CIE_xyY = CIE_xyY {
+    x: 0.3127,
+    y: 0.3290,
+    Y: 1.0,
+};
+
+const DISPLAY_P3_PRIMARIES: CIE_xyYTRIPLE = CIE_xyYTRIPLE {
+    red: CIE_xyY {
+        x: 0.680,
+        y: 0.320,
+        Y: 1.0,
+    },
+    green: CIE_xyY {
+        x: 0.265,
+        y: 0.690,
+        Y: 1.0,
+    },
+    blue: CIE_xyY {
+        x: 0.150,
+        y: 0.060,
+        Y: 1.0,
+    },
+};
+
+// the primaries, their mixes and grays, which tell color spaces apart by how they are rendered
+const PROBE_COLORS: [[u8; 4]; 9] = [
+    [0, 0, 0, 255],
+    [255, 255, 255, 255],
+    [128, 128, 128, 255],
+    [255, 0, 0, 255],
+    [0, 255, 0, 255],
+    [0, 0, 255, 255],
+    [255, 255, 0, 255],
+    [0, 255, 255, 255],
+    [255, 0, 255, 255],
+];
+
+// the transfer function of sRGB, which Display P3 shares
+fn srgb_table() -> Vec<u16> {
+    (0..1024)
+        .map(|i| (yiq::linearize(i as f32 * 255.0 / 1023.0) / 255.0 * 65535.0).round() as u16)
+        .collect()
+}
+
+fn working_space_profile(color_space: &cli::ColorSpace) -> Option<Box<Profile>> {
+    let mut profile = match color_space {
+        cli::ColorSpace::Srgb => Profile::new_sRGB(),
+        cli::ColorSpace::DisplayP3 => {
+            Profile::new_rgb_with_table(D65, DISPLAY_P3_PRIMARIES, &srgb_table())?
+        }
+        cli::ColorSpace::Unmanaged => return None,
+    };
+    profile.precache_output_transform();
+    Some(profile)
+}
+
+// converts the pixels from the embedded profile into the working space, images without a profile
+// are taken to be sRGB
+pub fn to_working_space(
+    image: &mut RgbaImage,
+    profile: Option<&[u8]>,
+    color_space: &cli::ColorSpace,
+) {
+    if matches!(color_space, cli::ColorSpace::Unmanaged) {
+        return;
+    }
+
+    let input = match profile {
+        Some(profile) => match Profile::new_from_slice(profile, false) {
+            Some(input) => input,
+            None => {
+                eprintln!(
+                    "{} unsupported ICC profile \"{}\", the pixels are compared as they are",
+                    "Warning:".yellow(),
+                    description(profile).unwrap_or_default().magenta()
+                );
+                return;
+            }
+        },
+        None if matches!(color_space, cli::ColorSpace::Srgb) => return,
+        None => Profile::new_sRGB(),
+    };
+
+    let transform = working_space_profile(color_space)
+        .and_then(|output| Transform::new(&input, &output, DataType::RGBA8, Intent::Perceptual));
+
+    match transform {
+        Some(transform) => transform.apply(image),
+        None => eprintln!(
+            "{} failed to convert from the ICC profile \"{}\", the pixels are compared as they are",
+            "Warning:".yellow(),
+            profile.and_then(description).unwrap_or_default().magenta()
+        ),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// the text of the `desc` tag, either an ICC v2 `desc` or an ICC v4 `mluc` of which the first
+// translation is taken
+fn description(profile: &[u8]) -> Option<String> {
+    let count = read_u32(profile, 128)? as usize;
+    let (offset, size) = (0..count.min(profile.len() / 12)).find_map(|i| {
+        let entry = 132 + i * 12;
+        match profile.get(entry..entry + 4)? {
+            b"desc" => Some((
+                read_u32(profile, entry + 4)? as usize,
+                read_u32(profile, entry + 8)? as usize,
+            )),
+            _ => None,
+        }
+    })?;
+    let tag = profile.get(offset..offset.checked_add(size)?)?;
+
+    let text = match tag.get(0..4)? {
+        b"desc" => {
+            let length = read_u32(tag, 8)? as usize;
+            let ascii = tag.get(12..12usize.checked_add(length)?)?;
+            String::from_utf8_lossy(ascii)
+                .trim_end_matches('\0')
+                .to_owned()
+        }
+        b"mluc" => {
+            let length = read_u32(tag, 20)? as usize;
+            let start = read_u32(tag, 24)? as usize;
+            let utf16: Vec<u16> = tag
+                .get(start..start.checked_add(length)?)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&utf16)
+                .trim_end_matches('\0')
+                .to_owned()
+        }
+        _ => return None,
+    };
+
+    Some(text)
+}
+
+// the probe colors converted from the profile into sRGB, which images without a profile are
+// taken to be in; None for unsupported profiles
+fn probe(profile: Option<&[u8]>) -> Option<RgbaImage> {
+    let mut probe = RgbaImage::from_fn(PROBE_COLORS.len() as u32, 1, |x, _| {
+        Rgba(PROBE_COLORS[x as usize])
+    });
+
+    if let Some(profile) = profile {
+        let input = Profile::new_from_slice(profile, false)?;
+        let output = working_space_profile(&cli::ColorSpace::Srgb)?;
+        Transform::new(&input, &output, DataType::RGBA8, Intent::Perceptual)?.apply(&mut probe);
+    }
+
+    Some(probe)
+}
+
+// profiles of the same color space differ in their bytes as often as not, e.g. a tagged and an
+// untagged sRGB image, so they are compared by how they render the probe colors instead, allowing
+// for rounding
+fn same_color_space(left: Option<&[u8]>, right: Option<&[u8]>) -> bool {
+    if left == right {
+        return true;
+    }
+
+    match (probe(left), probe(right)) {
+        (Some(left), Some(right)) => left
+            .as_raw()
+            .iter()
+            .zip(right.as_raw().iter())
+            .all(|(left, right)| left.abs_diff(*right) <= 1),
+        _ => false,
+    }
+}
+
+// warns about images that are not in the same color space, whose pixels are not comparable
+// without converting them
+pub fn check_profiles(left: Option<&[u8]>, right: Option<&[u8]>) {
+    if same_color_space(left, right) {
+        return;
+    }
+
+    let name = |profile: Option<&[u8]>| match profile {
+        Some(profile) => description(profile).unwrap_or_else(|| "unnamed".to_owned()),
+        None => "none".to_owned(),
+    };
+
+    eprintln!(
+        "{} the color profiles differ, left: {}, right: {}",
+        "Warning:".yellow(),
+        name(left).magenta(),
+        name(right).magenta()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageDecoder, ImageReader};
+
+    // a profile with nothing but a `desc` tag of the given type and content
+    fn profile_with_description(tag: &[u8]) -> Vec<u8> {
+        let mut profile = vec![0; 128];
+        profile.extend(1u32.to_be_bytes().iter());
+        profile.extend(b"desc".iter());
+        profile.extend(144u32.to_be_bytes().iter());
+        profile.extend((tag.len() as u32).to_be_bytes().iter());
+        profile.extend(tag.iter());
+        profile
+    }
+
+    #[test]
+    fn test_description() {
+        let mut desc = b"desc\0\0\0\0".to_vec();
+        desc.extend(5u32.to_be_bytes().iter());
+        desc.extend(b"sRGB\0".iter());
+        assert_eq!(
+            Some("sRGB".to_owned()),
+            description(&profile_with_description(&desc))
+        );
+
+        let mut mluc = b"mluc\0\0\0\0".to_vec();
+        mluc.extend(1u32.to_be_bytes().iter());
+        mluc.extend(12u32.to_be_bytes().iter());
+        mluc.extend(b"enUS".iter());
+        mluc.extend(8u32.to_be_bytes().iter());
+        mluc.extend(28u32.to_be_bytes().iter());
+        mluc.extend([0, b'P', 0, b'3', 0, b'!', 0, 0].iter());
+        assert_eq!(
+            Some("P3!".to_owned()),
+            description(&profile_with_description(&mluc))
+        );
+
+        assert_eq!(None, description(&[0; 64]));
+    }
+
+    // the sRGB profile embedded in one of the fixtures
+    fn srgb_profile() -> Vec<u8> {
+        ImageReader::open("./benches/fixtures/tiger.jpg")
+            .unwrap()
+            .into_decoder()
+            .unwrap()
+            .icc_profile()
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_same_color_space() {
+        let srgb = srgb_profile();
+        assert!(same_color_space(None, None));
+        assert!(same_color_space(Some(&srgb), None));
+        assert!(same_color_space(None, Some(&srgb)));
+
+        // the same profile with the red and blue primaries swapped
+        let mut swapped = srgb.clone();
+        let count = read_u32(&srgb, 128).unwrap() as usize;
+        let entry = |signature: &[u8]| {
+            (0..count)
+                .map(|i| 132 + i * 12)
+                .find(|entry| &srgb[*entry..*entry + 4] == signature)
+                .unwrap()
+        };
+        let (red, blue) = (entry(b"rXYZ"), entry(b"bXYZ"));
+        swapped[red + 4..red + 12].copy_from_slice(&srgb[blue + 4..blue + 12]);
+        swapped[blue + 4..blue + 12].copy_from_slice(&srgb[red + 4..red + 12]);
+        assert!(!same_color_space(Some(&swapped), None));
+        assert!(!same_color_space(Some(&swapped), Some(&srgb)));
+
+        // unsupported profiles cannot be told apart
+        assert!(!same_color_space(Some(&[0; 64]), None));
+    }
+
+    #[test]
+    fn test_to_working_space() {
+        let color = Rgba([200, 100, 50, 255]);
+
+        // untagged images are sRGB already
+        let mut image = RgbaImage::from_pixel(1, 1, color);
+        to_working_space(&mut image, None, &cli::ColorSpace::Srgb);
+        assert_eq!(color, *image.get_pixel(0, 0));
+
+        // the same color takes less saturated values in the wider gamut of Display P3
+        let mut image = RgbaImage::from_pixel(1, 1, color);
+        to_working_space(&mut image, None, &cli::ColorSpace::DisplayP3);
+        let Rgba([r, g, b, a]) = *image.get_pixel(0, 0);
+        assert!(r < 200 && g > 100 && b > 50 && a == 255);
+
+        let mut image = RgbaImage::from_pixel(1, 1, color);
+        to_working_space(&mut image, None, &cli::ColorSpace::Unmanaged);
+        assert_eq!(color, *image.get_pixel(0, 0));
+    }
+}
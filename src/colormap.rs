@@ -0,0 +1,42 @@
+use image::Rgba;
+
+// samples of matplotlib's "inferno" colormap, evenly spaced from 0 to 1
+const INFERNO: [[u8; 3]; 9] = [
+    [0, 0, 4],
+    [31, 12, 72],
+    [85, 15, 109],
+    [136, 34, 106],
+    [186, 54, 85],
+    [227, 89, 51],
+    [249, 140, 10],
+    [249, 201, 50],
+    [252, 255, 164],
+];
+
+pub fn inferno(value: f32) -> Rgba<u8> {
+    let position = value.clamp(0.0, 1.0) * (INFERNO.len() - 1) as f32;
+    let index = (position as usize).min(INFERNO.len() - 2);
+    let fraction = position - index as f32;
+    let (from, to) = (INFERNO[index], INFERNO[index + 1]);
+    let channel = |i: usize| {
+        (f32::from(from[i]) + (f32::from(to[i]) - f32::from(from[i])) * fraction).round() as u8
+    };
+
+    Rgba([channel(0), channel(1), channel(2), u8::MAX])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::inferno;
+    use image::Rgba;
+
+    #[test]
+    fn test_inferno() {
+        assert_eq!(Rgba([0, 0, 4, 255]), inferno(0.0));
+        assert_eq!(Rgba([186, 54, 85, 255]), inferno(0.5));
+        assert_eq!(Rgba([252, 255, 164, 255]), inferno(1.0));
+        assert_eq!(Rgba([16, 6, 38, 255]), inferno(0.0625));
+        assert_eq!(inferno(0.0), inferno(-1.0));
+        assert_eq!(inferno(1.0), inferno(2.0));
+    }
+}
@@ -0,0 +1,285 @@
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use glob::Pattern;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = "dify.toml";
+const KEY_RULES: &str = "rules";
+const KEY_FILES: &str = "files";
+// settings naming files, which are relative to the directory of the config file
+const PATH_KEYS: [&str; 4] = ["output", "mask-output", "flicker", "block-out-mask"];
+
+// settings are keyed by the long names of the command line options, e.g.
+//
+//     threshold = 0.2
+//     detect-anti-aliased = true
+//     output = "diff.png"
+//
+//     [[rules]]
+//     files = "screens/clock-*.png"
+//     block-out = ["10,10,50,20"]
+//
+// rules apply to comparisons where the left or right path matches the glob of `files`, relative
+// to the directory of the config file
+pub struct Config {
+    path: PathBuf,
+    table: toml::Table,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path).with_context(|| {
+            format!(
+                "failed to read config file \"{}\"",
+                path.display().to_string().magenta()
+            )
+            .red()
+        })?;
+
+        let table = content.parse::<toml::Table>().with_context(|| {
+            format!(
+                "failed to parse config file \"{}\"",
+                path.display().to_string().magenta()
+            )
+            .red()
+        })?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            table,
+        })
+    }
+
+    // looks for the config file in the working directory and its ancestors
+    pub fn discover() -> Result<Option<Self>> {
+        let cwd = env::current_dir()?;
+
+        cwd.ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file())
+            .map(|path| Self::load(&path))
+            .transpose()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn matches(&self, pattern: &Pattern, file: &str) -> bool {
+        if pattern.matches(file) {
+            return true;
+        }
+
+        let dir = self.path.parent().and_then(|dir| dir.canonicalize().ok());
+        let file = Path::new(file).canonicalize().ok();
+
+        match (dir, file) {
+            (Some(dir), Some(file)) => file
+                .strip_prefix(dir)
+                .map(|file| pattern.matches_path(file))
+                .unwrap_or(false),
+            _ => false,
+        }
+    }
+
+    // converts the settings into command line arguments, `is_present` tells whether an option is
+    // already given on the command line; single values given there win over matching rules, which
+    // win over top-level settings, while lists are appended
+    pub fn to_args(
+        &self,
+        files: &[&str],
+        is_present: impl Fn(&str) -> bool,
+    ) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let mut given: HashSet<&str> = HashSet::new();
+
+        let rules = match self.table.get(KEY_RULES) {
+            Some(toml::Value::Array(rules)) => rules.as_slice(),
+            Some(_) => return Err(self.invalid(KEY_RULES, "should be an array of tables")),
+            None => &[],
+        };
+
+        for rule in rules {
+            let rule = rule
+                .as_table()
+                .ok_or_else(|| self.invalid(KEY_RULES, "should be an array of tables"))?;
+            let pattern = rule
+                .get(KEY_FILES)
+                .and_then(|files| files.as_str())
+                .ok_or_else(|| self.invalid(KEY_FILES, "should be a glob pattern"))?;
+            let pattern = Pattern::new(pattern)
+                .map_err(|error| self.invalid(KEY_FILES, &error.to_string()))?;
+
+            if files.iter().any(|file| self.matches(&pattern, file)) {
+                for (key, value) in rule.iter().filter(|(key, _)| key.as_str() != KEY_FILES) {
+                    self.push_args(&mut args, &mut given, key, value, &is_present)?;
+                }
+            }
+        }
+
+        for (key, value) in self
+            .table
+            .iter()
+            .filter(|(key, _)| key.as_str() != KEY_RULES)
+        {
+            self.push_args(&mut args, &mut given, key, value, &is_present)?;
+        }
+
+        Ok(args)
+    }
+
+    fn push_args<'a>(
+        &self,
+        args: &mut Vec<String>,
+        given: &mut HashSet<&'a str>,
+        key: &'a str,
+        value: &toml::Value,
+        is_present: impl Fn(&str) -> bool,
+    ) -> Result<()> {
+        match value {
+            toml::Value::Array(values) => {
+                for value in values {
+                    args.push(format!("--{}={}", key, self.to_arg(key, value)?));
+                }
+            }
+            _ if is_present(key) || given.contains(key) => (),
+            toml::Value::Boolean(true) => {
+                args.push(format!("--{key}"));
+                given.insert(key);
+            }
+            toml::Value::Boolean(false) => {
+                given.insert(key);
+            }
+            value => {
+                args.push(format!("--{}={}", key, self.to_arg(key, value)?));
+                given.insert(key);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn to_arg(&self, key: &str, value: &toml::Value) -> Result<String> {
+        match value {
+            toml::Value::String(value) if PATH_KEYS.contains(&key) => Ok(self
+                .path
+                .parent()
+                .map_or_else(|| PathBuf::from(value), |dir| dir.join(value))
+                .display()
+                .to_string()),
+            toml::Value::String(value) => Ok(value.clone()),
+            toml::Value::Integer(value) => Ok(value.to_string()),
+            toml::Value::Float(value) => Ok(value.to_string()),
+            _ => Err(self.invalid(key, "should be a string or a number")),
+        }
+    }
+
+    fn invalid(&self, key: &str, reason: &str) -> anyhow::Error {
+        anyhow!(format!(
+            "the value of {} in config file \"{}\" {}",
+            key.magenta(),
+            self.path.display().to_string().magenta(),
+            reason
+        )
+        .red())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(content: &str) -> Config {
+        Config {
+            path: PathBuf::from("/nonexistent").join(FILE_NAME),
+            table: content.parse::<toml::Table>().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_to_args() {
+        let config = config(
+            r#"
+            threshold = 0.2
+            detect-anti-aliased = true
+            labels = false
+            output = "out/diff.png"
+            block-out = ["1,2,3,4", "5,6,7,8"]
+            "#,
+        );
+
+        let mut actual = config.to_args(&["a.png", "b.png"], |_| false).unwrap();
+        actual.sort();
+
+        assert_eq!(
+            vec![
+                "--block-out=1,2,3,4",
+                "--block-out=5,6,7,8",
+                "--detect-anti-aliased",
+                &format!(
+                    "--output={}",
+                    Path::new("/nonexistent").join("out/diff.png").display()
+                ),
+                "--threshold=0.2",
+            ],
+            actual
+        );
+    }
+
+    #[test]
+    fn test_to_args_command_line_wins() {
+        let config = config(
+            r#"
+            threshold = 0.2
+            block-out = ["1,2,3,4"]
+            "#,
+        );
+
+        let mut actual = config
+            .to_args(&["a.png", "b.png"], |key| {
+                key == "threshold" || key == "block-out"
+            })
+            .unwrap();
+        actual.sort();
+
+        assert_eq!(vec!["--block-out=1,2,3,4"], actual);
+    }
+
+    #[test]
+    fn test_to_args_rules() {
+        let config = config(
+            r#"
+            block-out = ["1,2,3,4"]
+
+            [[rules]]
+            files = "screens/clock-*.png"
+            block-out = ["5,6,7,8"]
+
+            [[rules]]
+            files = "charts/*.png"
+            block-out = ["9,9,9,9"]
+            "#,
+        );
+
+        let mut actual = config
+            .to_args(&["baseline/clock-1.png", "screens/clock-1.png"], |_| false)
+            .unwrap();
+        actual.sort();
+
+        assert_eq!(vec!["--block-out=1,2,3,4", "--block-out=5,6,7,8"], actual);
+    }
+
+    #[test]
+    fn test_to_args_invalid() {
+        assert!(config("threshold = [true]")
+            .to_args(&[], |_| false)
+            .is_err());
+        assert!(config("rules = 1").to_args(&[], |_| false).is_err());
+        assert!(config("[[rules]]\nblock-out = []")
+            .to_args(&[], |_| false)
+            .is_err());
+    }
+}
@@ -1,23 +1,34 @@
-use super::{antialiased, cli, yiq::Yiq};
+use super::animation::{self, Frame};
+use super::{
+    antialiased, cli, color, colormap, histogram, layout, shifted_match, text,
+    yiq::{self, Yiq},
+};
 use anyhow::{anyhow, Context, Result};
 use colored::*;
-use image::{GenericImageView, ImageBuffer, ImageFormat, ImageReader, Pixel, Rgba, RgbaImage};
+use image::{
+    imageops, metadata::Orientation, DynamicImage, GenericImageView, GrayImage, ImageBuffer,
+    ImageFormat, Luma, Pixel, Rgba, RgbaImage,
+};
 use std::collections::HashSet;
+use std::convert::TryFrom;
 
-const MAX_YIQ_POSSIBLE_DELTA: f32 = 35215.0;
-const RED_PIXEL: Rgba<u8> = Rgba([255, 0, 0, 255]);
-const YELLOW_PIXEL: Rgba<u8> = Rgba([255, 255, 0, 255]);
+const MAX_YIQ_POSSIBLE_DELTA: f32 = yiq::MAX_SQUARED_DISTANCE;
+pub const RED_PIXEL: Rgba<u8> = Rgba([255, 0, 0, 255]);
+pub const YELLOW_PIXEL: Rgba<u8> = Rgba([255, 255, 0, 255]);
+const MASK_DIFFERENT: Luma<u8> = Luma([255]);
+const MASK_ANTI_ALIASED: Luma<u8> = Luma([128]);
 
 #[derive(Debug, PartialEq)]
 pub enum DiffResult {
     Identical(u32, u32),
-    BelowThreshold(u32, u32),
-    Different(u32, u32),
+    BelowThreshold(u32, u32, f32),
+    Different(u32, u32, f32),
     OutOfBounds(u32, u32),
     AntiAliased(u32, u32),
     BlockedOut(u32, u32),
 }
 
+#[derive(Clone)]
 pub struct RunParams<'a> {
     pub left: &'a str,
     pub right: &'a str,
@@ -26,59 +37,260 @@ pub struct RunParams<'a> {
     pub output_image_base: Option<cli::OutputImageBase>,
     pub do_not_check_dimensions: bool,
     pub detect_anti_aliased_pixels: bool,
+    pub anti_aliasing: cli::AntiAliasing,
     pub blend_factor_of_unchanged_pixels: Option<f32>,
     pub block_out_areas: Option<HashSet<(u32, u32)>>,
+    pub background: cli::Background,
+    pub style: cli::DiffStyle,
+    pub diff_color: Rgba<u8>,
+    pub diff_color_alt: Option<Rgba<u8>>,
+    pub anti_aliased_color: Rgba<u8>,
+    pub block_out_color: Option<Rgba<u8>>,
+    pub layout: cli::Layout,
+    pub flicker: Option<cli::Flicker>,
+    pub mask_output: Option<String>,
+    pub crop_to_diff: Option<u32>,
+    pub print_metrics: bool,
+    pub anti_aliased_budget: Option<u32>,
+    pub text_tolerance: Option<u32>,
+    pub shift_tolerance: Option<u32>,
+    pub ignore_colors: Vec<cli::IgnoreColor>,
+    pub luminance_only: bool,
+    pub linear: bool,
+    pub ignore_orientation: bool,
+    pub metric: Option<cli::Metric>,
+    pub color_space: cli::ColorSpace,
 }
 
-fn open_and_decode_image(path: &str, which: &str) -> Result<RgbaImage> {
-    let image = ImageReader::open(path)
-        .with_context(|| format!("failed to open {} image \"{}\"", which, path.magenta()).red())?
-        .decode()
-        .with_context(|| format!("failed to decode {} image \"{}\"", which, path.magenta()).red())?
-        .to_rgba8();
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Metrics {
+    // mean squared error of the red, green and blue channels, on a scale of 0 to 255 like ffmpeg
+    pub mse: f64,
+    // peak signal-to-noise ratio in dB, infinite for identical images
+    pub psnr: f64,
+    // root mean squared error of the red, green, blue and alpha channel
+    pub rmse: [f64; 4],
+}
+
+impl Metrics {
+    fn from_squared_errors(squared_errors: [u64; 4], pixels: u64) -> Self {
+        let mut channel_mse = [0.0; 4];
+        for (mse, squared_error) in channel_mse.iter_mut().zip(squared_errors.iter()) {
+            *mse = match pixels {
+                0 => 0.0,
+                _ => *squared_error as f64 / pixels as f64,
+            };
+        }
+
+        let mse = channel_mse[..3].iter().sum::<f64>() / 3.0;
+        let mut rmse = [0.0; 4];
+        for (rmse, mse) in rmse.iter_mut().zip(channel_mse.iter()) {
+            *rmse = mse.sqrt();
+        }
 
-    Ok(image)
+        Self {
+            mse,
+            psnr: 10.0 * (255.0 * 255.0 / mse).log10(),
+            rmse,
+        }
+    }
+}
+
+impl std::fmt::Display for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "mse: {:.4}, psnr: {:.2} dB, rmse: r {:.4} g {:.4} b {:.4} a {:.4}",
+            self.mse, self.psnr, self.rmse[0], self.rmse[1], self.rmse[2], self.rmse[3]
+        )
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Output {
+    pub diffs: i32,
+    // pixels told apart by anti-aliasing only, which do not count as different
+    pub anti_aliased: i32,
+    pub image: RgbaImage,
+    // white for different pixels, gray for anti-aliased ones and black for the rest
+    pub mask: Option<GrayImage>,
+    // x, y, width and height of the smallest area containing all different pixels
+    pub bounding_box: Option<(u32, u32, u32, u32)>,
+}
+
+fn orient(image: RgbaImage, orientation: Orientation) -> RgbaImage {
+    let mut image = DynamicImage::ImageRgba8(image);
+    image.apply_orientation(orientation);
+    image.into_rgba8()
+}
+
+// every frame of the image, rotated and flipped by its EXIF orientation unless ignored and
+// converted from its color profile, which is kept for telling whether images are comparable
+fn open_and_decode_frames(
+    path: &str,
+    which: &str,
+    color_space: &cli::ColorSpace,
+    ignore_orientation: bool,
+) -> Result<animation::Decoded> {
+    let mut decoded = animation::read(path, which)?;
+
+    for frame in decoded.frames.iter_mut() {
+        color::to_working_space(&mut frame.image, decoded.profile.as_deref(), color_space);
+        if !ignore_orientation {
+            frame.image = orient(std::mem::take(&mut frame.image), frame.orientation);
+        }
+    }
+
+    Ok(decoded)
+}
+
+fn first_frame(frames: Vec<Frame>) -> RgbaImage {
+    frames
+        .into_iter()
+        .next()
+        .map(|frame| frame.image)
+        .unwrap_or_default()
+}
+
+// the first frame of the image, see `open_and_decode_frames`
+pub(crate) fn open_and_decode_image(
+    path: &str,
+    which: &str,
+    color_space: &cli::ColorSpace,
+    ignore_orientation: bool,
+) -> Result<RgbaImage> {
+    open_and_decode_frames(path, which, color_space, ignore_orientation)
+        .map(|decoded| first_frame(decoded.frames))
+}
+
+// the frames of the left and right image, warning when they are in different color spaces
+fn open_and_decode_both_frames(
+    left: &str,
+    right: &str,
+    color_space: &cli::ColorSpace,
+    ignore_orientation: bool,
+) -> Result<(Vec<Frame>, Vec<Frame>)> {
+    let (left, right) = rayon::join(
+        || open_and_decode_frames(left, "left", color_space, ignore_orientation),
+        || open_and_decode_frames(right, "right", color_space, ignore_orientation),
+    );
+    let (left, right) = (left?, right?);
+
+    color::check_profiles(left.profile.as_deref(), right.profile.as_deref());
+    Ok((left.frames, right.frames))
+}
+
+// the first frames of the left and right image, see `open_and_decode_both_frames`
+pub(crate) fn open_and_decode_images(
+    left: &str,
+    right: &str,
+    color_space: &cli::ColorSpace,
+    ignore_orientation: bool,
+) -> Result<(RgbaImage, RgbaImage)> {
+    open_and_decode_both_frames(left, right, color_space, ignore_orientation)
+        .map(|(left, right)| (first_frame(left), first_frame(right)))
+}
+
+// maps the delta onto the same scale as the threshold, i.e. a pixel whose delta equals the
+// threshold gets the color at the threshold's position in the colormap
+fn heatmap_pixel(delta: f32) -> Rgba<u8> {
+    colormap::inferno((delta.abs() / MAX_YIQ_POSSIBLE_DELTA).sqrt())
+}
+
+// mid gray for no difference, darker or lighter as far as the right pixel is darker or lighter
+// than the left one, on the same scale as the heatmap
+fn luminance_pixel(delta: f32) -> Rgba<u8> {
+    let level = 127.5 + delta.signum() * (delta.abs() / MAX_YIQ_POSSIBLE_DELTA).sqrt() * 127.5;
+    let level = level.round() as u8;
+    Rgba([level, level, level, u8::MAX])
+}
+
+fn blend_unchanged_pixel(left_image: &RgbaImage, x: u32, y: u32, alpha: f32) -> Rgba<u8> {
+    let left_pixel = left_image.get_pixel(x, y);
+    let yiq_y = Yiq::rgb2y(&left_pixel.to_rgb());
+    let rgba_a = left_pixel.channels()[3] as f32;
+    let color = super::blend_semi_transparent_white(yiq_y, alpha * rgba_a / 255.0) as u8;
+
+    Rgba([color, color, color, u8::MAX])
 }
 
 pub fn get_results(
-    left_image: RgbaImage,
-    right_image: RgbaImage,
-    threshold: f32,
-    detect_anti_aliased_pixels: bool,
-    blend_factor_of_unchanged_pixels: Option<f32>,
-    output_image_base: &Option<cli::OutputImageBase>,
-    block_out_areas: &Option<HashSet<(u32, u32)>>,
-) -> Option<(i32, RgbaImage)> {
+    left_image: &RgbaImage,
+    right_image: &RgbaImage,
+    params: &RunParams,
+) -> Option<Output> {
+    get_results_and_metrics(left_image, right_image, params).0
+}
+
+// the metrics are computed over the pixels of the left image which are within the bounds of the
+// right one and not blocked out, and are available even when there is no output
+pub fn get_results_and_metrics(
+    left_image: &RgbaImage,
+    right_image: &RgbaImage,
+    params: &RunParams,
+) -> (Option<Output>, Metrics) {
     let (width, height) = left_image.dimensions();
+    let threshold = MAX_YIQ_POSSIBLE_DELTA * params.threshold * params.threshold;
+    let background = &params.background;
+    let aa = &params.anti_aliasing;
+    let measure = yiq::Measure {
+        linear: params.linear,
+        luminance_only: params.luminance_only,
+    };
 
     let results = left_image.enumerate_pixels().map(|(x, y, left_pixel)| {
         if right_image.in_bounds(x, y) {
             let right_pixel = right_image.get_pixel(x, y);
 
-            if left_pixel == right_pixel {
-                DiffResult::Identical(x, y)
-            } else if block_out_areas
+            if params
+                .block_out_areas
                 .as_ref()
                 .and_then(|set| set.contains(&(x, y)).then_some(()))
                 .is_some()
             {
                 DiffResult::BlockedOut(x, y)
+            } else if left_pixel == right_pixel {
+                DiffResult::Identical(x, y)
+            } else if params
+                .ignore_colors
+                .iter()
+                .any(|ignore| ignore.matches(left_pixel) || ignore.matches(right_pixel))
+            {
+                DiffResult::BlockedOut(x, y)
             } else {
-                let left_pixel = Yiq::from_rgba(left_pixel);
-                let right_pixel = Yiq::from_rgba(right_pixel);
-                let delta = left_pixel.squared_distance(&right_pixel);
+                let background_color = background.at(x, y);
+                let left_pixel = measure.yiq(left_pixel, background_color);
+                let right_pixel = measure.yiq(right_pixel, background_color);
+                let delta = measure.delta(&left_pixel, &right_pixel);
 
                 if delta.abs() > threshold {
-                    if detect_anti_aliased_pixels
-                        && (antialiased(&left_image, x, y, width, height, &right_image)
-                            || antialiased(&right_image, x, y, width, height, &left_image))
+                    let shifted = params
+                        .shift_tolerance
+                        .and_then(|radius| {
+                            shifted_match(
+                                left_image,
+                                x,
+                                y,
+                                right_image,
+                                radius,
+                                background,
+                                &measure,
+                            )
+                        })
+                        .filter(|delta| delta.abs() <= threshold);
+
+                    if let Some(delta) = shifted {
+                        DiffResult::BelowThreshold(x, y, delta)
+                    } else if params.detect_anti_aliased_pixels
+                        && (antialiased(left_image, x, y, right_image, background, aa, &measure)
+                            || antialiased(right_image, x, y, left_image, background, aa, &measure))
                     {
                         DiffResult::AntiAliased(x, y)
                     } else {
-                        DiffResult::Different(x, y)
+                        DiffResult::Different(x, y, delta)
                     }
                 } else {
-                    DiffResult::BelowThreshold(x, y)
+                    DiffResult::BelowThreshold(x, y, delta)
                 }
             }
         } else {
@@ -86,52 +298,156 @@ pub fn get_results(
         }
     });
 
+    // telling fringes of glyphs from other differences needs all of the results at once
+    let results: Box<dyn Iterator<Item = DiffResult> + '_> = match params.text_tolerance {
+        Some(band_width) => Box::new(
+            text::tolerate(
+                left_image,
+                results.collect(),
+                band_width,
+                background,
+                &measure,
+            )
+            .into_iter(),
+        ),
+        None => Box::new(results),
+    };
+
     let mut diffs: i32 = 0;
+    let mut anti_aliased: i32 = 0;
+    let mut squared_errors = [0u64; 4];
+    let mut compared_pixels: u64 = 0;
+    // whether changes below the threshold are drawn, which is worth an output of its own
+    let mut shaded = false;
+    let mut corners: Option<((u32, u32), (u32, u32))> = None;
+    let mut include = |x: u32, y: u32| {
+        corners = Some(match corners {
+            None => ((x, y), (x, y)),
+            Some(((x0, y0), (x1, y1))) => ((x0.min(x), y0.min(y)), (x1.max(x), y1.max(y))),
+        });
+    };
 
-    let mut output_image = match output_image_base {
+    let mut output_image = match params.output_image_base {
         Some(cli::OutputImageBase::LeftImage) => left_image.clone(),
         Some(cli::OutputImageBase::RightImage) => right_image.clone(),
         None => ImageBuffer::new(width, height),
     };
 
+    let mut mask = params
+        .mask_output
+        .as_ref()
+        .map(|_| GrayImage::new(width, height));
+
     for result in results {
         match result {
-            DiffResult::Identical(x, y) | DiffResult::BelowThreshold(x, y) => {
-                if let Some(alpha) = blend_factor_of_unchanged_pixels {
-                    let left_pixel = left_image.get_pixel(x, y);
-                    let yiq_y = Yiq::rgb2y(&left_pixel.to_rgb());
-                    let rgba_a = left_pixel.channels()[3] as f32;
-                    let color =
-                        super::blend_semi_transparent_white(yiq_y, alpha * rgba_a / 255.0) as u8;
-
-                    output_image.put_pixel(x, y, Rgba([color, color, color, u8::MAX]));
+            DiffResult::OutOfBounds(..) | DiffResult::BlockedOut(..) => (),
+            DiffResult::Identical(..) => compared_pixels += 1,
+            DiffResult::BelowThreshold(x, y, _)
+            | DiffResult::Different(x, y, _)
+            | DiffResult::AntiAliased(x, y) => {
+                let left_pixel = left_image.get_pixel(x, y);
+                let right_pixel = right_image.get_pixel(x, y);
+                for (squared_error, (left, right)) in squared_errors
+                    .iter_mut()
+                    .zip(left_pixel.0.iter().zip(right_pixel.0.iter()))
+                {
+                    let error = u64::from(left.abs_diff(*right));
+                    *squared_error += error * error;
+                }
+                compared_pixels += 1;
+            }
+        }
+
+        match result {
+            DiffResult::BelowThreshold(x, y, delta)
+                if delta != 0.0 && matches!(params.style, cli::DiffStyle::Heatmap) =>
+            {
+                output_image.put_pixel(x, y, heatmap_pixel(delta));
+                shaded = true;
+            }
+            DiffResult::BelowThreshold(x, y, delta)
+                if delta != 0.0 && matches!(params.style, cli::DiffStyle::Luminance) =>
+            {
+                output_image.put_pixel(x, y, luminance_pixel(delta));
+                shaded = true;
+            }
+            DiffResult::Identical(x, y) | DiffResult::BelowThreshold(x, y, _) => {
+                if let Some(alpha) = params.blend_factor_of_unchanged_pixels {
+                    output_image.put_pixel(x, y, blend_unchanged_pixel(left_image, x, y, alpha));
+                }
+            }
+            DiffResult::BlockedOut(x, y) => {
+                match (
+                    params.block_out_color,
+                    params.blend_factor_of_unchanged_pixels,
+                ) {
+                    (Some(color), _) => output_image.put_pixel(x, y, color),
+                    (None, Some(alpha)) => {
+                        output_image.put_pixel(x, y, blend_unchanged_pixel(left_image, x, y, alpha))
+                    }
+                    (None, None) => (),
                 }
             }
-            DiffResult::Different(x, y) | DiffResult::OutOfBounds(x, y) => {
-                output_image.put_pixel(x, y, RED_PIXEL);
+            DiffResult::Different(x, y, delta) => {
+                let pixel = match (&params.style, params.diff_color_alt) {
+                    (cli::DiffStyle::Heatmap, _) => heatmap_pixel(delta),
+                    (cli::DiffStyle::Luminance, _) => luminance_pixel(delta),
+                    // a negative delta means the right pixel is darker than the left one
+                    (cli::DiffStyle::Binary, Some(color)) if delta < 0.0 => color,
+                    (cli::DiffStyle::Binary, _) => params.diff_color,
+                };
+                output_image.put_pixel(x, y, pixel);
+                if let Some(mask) = &mut mask {
+                    mask.put_pixel(x, y, MASK_DIFFERENT);
+                }
+                include(x, y);
+                diffs += 1;
+            }
+            DiffResult::OutOfBounds(x, y) => {
+                output_image.put_pixel(x, y, params.diff_color);
+                if let Some(mask) = &mut mask {
+                    mask.put_pixel(x, y, MASK_DIFFERENT);
+                }
+                include(x, y);
                 diffs += 1;
             }
             DiffResult::AntiAliased(x, y) => {
-                output_image.put_pixel(x, y, YELLOW_PIXEL);
+                output_image.put_pixel(x, y, params.anti_aliased_color);
+                if let Some(mask) = &mut mask {
+                    mask.put_pixel(x, y, MASK_ANTI_ALIASED);
+                }
+                anti_aliased += 1;
             }
-            DiffResult::BlockedOut(_x, _y) => (),
         }
     }
 
-    if diffs > 0 || blend_factor_of_unchanged_pixels.is_some() {
-        Some((diffs, output_image))
+    let output = if diffs > 0
+        || anti_aliased > 0
+        || shaded
+        || params.blend_factor_of_unchanged_pixels.is_some()
+    {
+        Some(Output {
+            diffs,
+            anti_aliased,
+            image: output_image,
+            mask,
+            bounding_box: corners.map(|((x0, y0), (x1, y1))| (x0, y0, x1 - x0 + 1, y1 - y0 + 1)),
+        })
     } else {
         None
-    }
-}
+    };
 
-pub fn run(params: &RunParams) -> Result<Option<i32>> {
-    let (left_image, right_image): (Result<RgbaImage>, Result<RgbaImage>) = rayon::join(
-        || open_and_decode_image(params.left, "left"),
-        || open_and_decode_image(params.right, "right"),
-    );
+    (
+        output,
+        Metrics::from_squared_errors(squared_errors, compared_pixels),
+    )
+}
 
-    let (left_image, right_image) = (left_image?, right_image?);
+pub(crate) fn check_dimensions(
+    left_image: &RgbaImage,
+    right_image: &RgbaImage,
+    params: &RunParams,
+) -> Result<()> {
     let left_dimensions = left_image.dimensions();
     let right_dimensions = right_image.dimensions();
 
@@ -144,26 +460,292 @@ pub fn run(params: &RunParams) -> Result<Option<i32>> {
         .red()));
     };
 
-    let threshold = MAX_YIQ_POSSIBLE_DELTA * params.threshold * params.threshold;
+    Ok(())
+}
+
+pub(crate) fn apply_layout(
+    left_image: &RgbaImage,
+    right_image: &RgbaImage,
+    output_image: RgbaImage,
+    params: &RunParams,
+) -> RgbaImage {
+    match params.layout {
+        cli::Layout::Diff => output_image,
+        cli::Layout::SideBySide { labels } => {
+            layout::side_by_side(left_image, right_image, &output_image, labels)
+        }
+    }
+}
+
+// fails when there are more anti-aliased pixels than the budget allows, to catch changes which
+// only move edges by a fraction of a pixel
+pub(crate) fn check_anti_aliased_budget(params: &RunParams, anti_aliased: i32) -> Result<()> {
+    match params.anti_aliased_budget {
+        Some(budget) if anti_aliased as u32 > budget => Err(anyhow!(format!(
+            "{} anti-aliased pixels exceed the budget of {}",
+            anti_aliased.to_string().magenta(),
+            budget.to_string().magenta()
+        )
+        .red())),
+        _ => Ok(()),
+    }
+}
+
+// outputs that only make sense for a single pair of images
+pub(crate) fn reject_single_image_outputs(params: &RunParams, what: &str) -> Result<()> {
+    if params.mask_output.is_some() || params.flicker.is_some() || params.crop_to_diff.is_some() {
+        return Err(anyhow!(format!(
+            "{}, {} and {} are not supported for {}",
+            "--mask-output".magenta(),
+            "--flicker".magenta(),
+            "--crop-to-diff".magenta(),
+            what
+        )
+        .red()));
+    }
+
+    Ok(())
+}
+
+// compares animations frame by frame and writes the diff as an animation with the timing of the
+// left one; frames missing on one side count as entirely different, while frames shown for a
+// different time are only reported
+fn run_animated(
+    params: &RunParams,
+    left_frames: &[Frame],
+    right_frames: &[Frame],
+) -> Result<Option<i32>> {
+    let skipped: Vec<String> = [
+        (params.mask_output.is_some(), "--mask-output"),
+        (params.flicker.is_some(), "--flicker"),
+        (params.crop_to_diff.is_some(), "--crop-to-diff"),
+    ]
+    .iter()
+    .filter(|(given, _)| *given)
+    .map(|(_, name)| name.magenta().to_string())
+    .collect();
+    if !skipped.is_empty() {
+        eprintln!(
+            "{} {} skipped for animated images",
+            "Warning:".yellow(),
+            skipped.join(", ")
+        );
+    }
+
+    if left_frames.len() != right_frames.len() {
+        println!(
+            "frame counts are different, left: {}, right: {}",
+            left_frames.len().to_string().magenta(),
+            right_frames.len().to_string().magenta()
+        );
+    }
+
+    let mut diffs: i32 = 0;
+    let mut anti_aliased: i32 = 0;
+    let mut rendered = false;
+    let mut output_frames: Vec<(RgbaImage, u16)> = Vec::new();
+
+    for index in 0..left_frames.len().max(right_frames.len()) {
+        let number = index + 1;
+
+        let (output_image, delay) = match (left_frames.get(index), right_frames.get(index)) {
+            (Some(left), Some(right)) => {
+                check_dimensions(&left.image, &right.image, params)
+                    .with_context(|| format!("in frame {}", number.to_string().magenta()).red())?;
+
+                if left.delay != right.delay {
+                    println!(
+                        "frame {}: delays are different, left: {}, right: {}",
+                        number,
+                        format!("{}ms", left.delay).magenta(),
+                        format!("{}ms", right.delay).magenta()
+                    );
+                }
+
+                let (output, metrics) = get_results_and_metrics(&left.image, &right.image, params);
+
+                match output.as_ref().map(|output| output.diffs) {
+                    Some(frame_diffs) if frame_diffs > 0 => {
+                        println!("frame {}: {} different pixels", number, frame_diffs);
+                        diffs += frame_diffs;
+                    }
+                    _ => println!("frame {}: no differences", number),
+                }
+
+                match output.as_ref().map(|output| output.anti_aliased) {
+                    Some(frame_anti_aliased) if frame_anti_aliased > 0 => {
+                        println!(
+                            "frame {}: {} anti-aliased pixels",
+                            number, frame_anti_aliased
+                        );
+                        anti_aliased += frame_anti_aliased;
+                    }
+                    _ => (),
+                }
+
+                if params.print_metrics {
+                    println!("frame {}: {}", number, metrics);
+                }
+
+                let output_image = match output {
+                    Some(output) => {
+                        rendered = true;
+                        output.image
+                    }
+                    None => RgbaImage::new(left.image.width(), left.image.height()),
+                };
+
+                (
+                    apply_layout(&left.image, &right.image, output_image, params),
+                    left.delay,
+                )
+            }
+            (Some(frame), None) | (None, Some(frame)) => {
+                let (width, height) = frame.image.dimensions();
+                let missing = RgbaImage::new(width, height);
+
+                println!("frame {}: missing on one side", number);
+                let pixels = u64::from(width) * u64::from(height);
+                diffs = diffs.saturating_add(i32::try_from(pixels).unwrap_or(i32::MAX));
+                rendered = true;
+
+                let output_image = RgbaImage::from_pixel(width, height, params.diff_color);
+                let output_image = match left_frames.get(index) {
+                    Some(_) => apply_layout(&frame.image, &missing, output_image, params),
+                    None => apply_layout(&missing, &frame.image, output_image, params),
+                };
+                (output_image, frame.delay)
+            }
+            (None, None) => unreachable!(),
+        };
+
+        output_frames.push((output_image, delay));
+    }
+
+    if !rendered {
+        return Ok(None);
+    }
+
+    let frames: Vec<(&RgbaImage, u16)> = output_frames
+        .iter()
+        .map(|(image, delay)| (image, *delay))
+        .collect();
+
+    animation::write(params.output, &frames).with_context(|| {
+        format!("failed to write diff image \"{}\"", params.output.magenta()).red()
+    })?;
+
+    check_anti_aliased_budget(params, anti_aliased)?;
+
+    Ok(Some(diffs))
+}
+
+fn write_mask(mask: &GrayImage, path: &str) -> Result<()> {
+    mask.save_with_format(path, ImageFormat::Png)
+        .with_context(|| format!("failed to write mask image \"{}\"", path.magenta()).red())
+}
+
+pub fn run(params: &RunParams) -> Result<Option<i32>> {
+    let (left_frames, right_frames) = open_and_decode_both_frames(
+        params.left,
+        params.right,
+        &params.color_space,
+        params.ignore_orientation,
+    )?;
+
+    if left_frames.len() > 1 || right_frames.len() > 1 {
+        return run_animated(params, &left_frames, &right_frames);
+    }
+
+    let (left_image, right_image) = (first_frame(left_frames), first_frame(right_frames));
+    let left_dimensions = left_image.dimensions();
+
+    check_dimensions(&left_image, &right_image, params)?;
+
+    let (output, metrics) = get_results_and_metrics(&left_image, &right_image, params);
+
+    if params.print_metrics {
+        println!("{}", metrics);
+    }
+
+    if let Some(cli::Metric::Histogram) = params.metric {
+        println!("{}", histogram::report(&left_image, &right_image));
+    }
+
+    match output {
+        Some(Output {
+            diffs,
+            anti_aliased,
+            image: output_image,
+            mask,
+            bounding_box,
+        }) => {
+            let (left_image, right_image, output_image, mask) =
+                match (params.crop_to_diff, bounding_box) {
+                    (Some(padding), Some((x, y, width, height))) => {
+                        let x0 = x.saturating_sub(padding);
+                        let y0 = y.saturating_sub(padding);
+                        let x1 = (x + width).saturating_add(padding).min(left_dimensions.0);
+                        let y1 = (y + height).saturating_add(padding).min(left_dimensions.1);
+                        let (width, height) = (x1 - x0, y1 - y0);
+
+                        println!("cropped to {}x{}+{}+{}", width, height, x0, y0);
+
+                        (
+                            imageops::crop_imm(&left_image, x0, y0, width, height).to_image(),
+                            imageops::crop_imm(&right_image, x0, y0, width, height).to_image(),
+                            imageops::crop_imm(&output_image, x0, y0, width, height).to_image(),
+                            mask.map(|mask| {
+                                imageops::crop_imm(&mask, x0, y0, width, height).to_image()
+                            }),
+                        )
+                    }
+                    _ => (left_image, right_image, output_image, mask),
+                };
+
+            if let (Some(mask), Some(path)) = (mask, &params.mask_output) {
+                write_mask(&mask, path)?;
+            }
+
+            if let Some(flicker) = &params.flicker {
+                let mut frames = vec![(&left_image, flicker.delay), (&right_image, flicker.delay)];
+                if flicker.with_diff {
+                    frames.push((&output_image, flicker.delay));
+                }
+
+                animation::write(&flicker.path, &frames).with_context(|| {
+                    format!(
+                        "failed to write flicker image \"{}\"",
+                        flicker.path.magenta()
+                    )
+                    .red()
+                })?;
+            }
+
+            let output_image = apply_layout(&left_image, &right_image, output_image, params);
 
-    match get_results(
-        left_image,
-        right_image,
-        threshold,
-        params.detect_anti_aliased_pixels,
-        params.blend_factor_of_unchanged_pixels,
-        &params.output_image_base,
-        &params.block_out_areas,
-    ) {
-        Some((diffs, output_image)) => {
             output_image
                 .save_with_format(params.output, ImageFormat::Png)
                 .with_context(|| {
                     format!("failed to write diff image \"{}\"", params.output.magenta()).red()
                 })?;
+
+            if anti_aliased > 0 {
+                println!("{} anti-aliased pixels", anti_aliased);
+            }
+            check_anti_aliased_budget(params, anti_aliased)?;
+
             Ok(Some(diffs))
         }
-        None => Ok(None),
+        None => {
+            // identical images still get a mask, with nothing marked on it
+            if let Some(path) = &params.mask_output {
+                let (width, height) = left_dimensions;
+                write_mask(&GrayImage::new(width, height), path)?;
+            }
+
+            Ok(None)
+        }
     }
 }
 
@@ -179,35 +761,44 @@ mod tests {
         output_image_base: None,
         do_not_check_dimensions: true,
         detect_anti_aliased_pixels: false,
+        anti_aliasing: cli::AntiAliasing {
+            radius: 1,
+            siblings: 3,
+            tolerance: 0.0,
+        },
         blend_factor_of_unchanged_pixels: None,
         block_out_areas: None,
+        background: cli::Background::White,
+        style: cli::DiffStyle::Binary,
+        diff_color: RED_PIXEL,
+        diff_color_alt: None,
+        anti_aliased_color: YELLOW_PIXEL,
+        block_out_color: None,
+        layout: cli::Layout::Diff,
+        flicker: None,
+        mask_output: None,
+        crop_to_diff: None,
+        print_metrics: false,
+        anti_aliased_budget: None,
+        text_tolerance: None,
+        shift_tolerance: None,
+        ignore_colors: Vec::new(),
+        luminance_only: false,
+        linear: false,
+        ignore_orientation: false,
+        metric: None,
+        color_space: cli::ColorSpace::Srgb,
     };
 
     #[test]
     fn test_zero_width_height() {
-        let actual = get_results(
-            RgbaImage::new(0, 0),
-            RgbaImage::new(0, 0),
-            RUN_PARAMS.threshold,
-            RUN_PARAMS.detect_anti_aliased_pixels,
-            RUN_PARAMS.blend_factor_of_unchanged_pixels,
-            &RUN_PARAMS.output_image_base,
-            &RUN_PARAMS.block_out_areas,
-        );
+        let actual = get_results(&RgbaImage::new(0, 0), &RgbaImage::new(0, 0), &RUN_PARAMS);
         assert_eq!(None, actual);
     }
 
     #[test]
     fn test_1_pixel() {
-        let actual = get_results(
-            RgbaImage::new(1, 1),
-            RgbaImage::new(1, 1),
-            RUN_PARAMS.threshold,
-            RUN_PARAMS.detect_anti_aliased_pixels,
-            RUN_PARAMS.blend_factor_of_unchanged_pixels,
-            &RUN_PARAMS.output_image_base,
-            &RUN_PARAMS.block_out_areas,
-        );
+        let actual = get_results(&RgbaImage::new(1, 1), &RgbaImage::new(1, 1), &RUN_PARAMS);
         assert_eq!(None, actual);
     }
 
@@ -215,19 +806,417 @@ mod tests {
     fn test_1_different() {
         let mut left = RgbaImage::new(1, 1);
         left.put_pixel(0, 0, YELLOW_PIXEL);
-        let actual = get_results(
-            left,
-            RgbaImage::new(1, 1),
-            RUN_PARAMS.threshold,
-            RUN_PARAMS.detect_anti_aliased_pixels,
-            RUN_PARAMS.blend_factor_of_unchanged_pixels,
-            &RUN_PARAMS.output_image_base,
-            &RUN_PARAMS.block_out_areas,
+        let actual = get_results(&left, &RgbaImage::new(1, 1), &RUN_PARAMS);
+
+        let mut expected_image = RgbaImage::new(1, 1);
+        expected_image.put_pixel(0, 0, RED_PIXEL);
+
+        assert_eq!(
+            Some((1, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
         );
+    }
+
+    #[test]
+    fn test_transparent_pixels_of_different_colors() {
+        let left = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 0]));
+        let right = RgbaImage::from_pixel(1, 1, Rgba([0, 0, 255, 0]));
+        let actual = get_results(&left, &right, &RUN_PARAMS);
+        assert_eq!(None, actual);
+    }
+
+    #[test]
+    fn test_semi_transparent_change() {
+        let left = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255]));
+        let right = RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 64]));
+        let actual = get_results(&left, &right, &RUN_PARAMS);
 
         let mut expected_image = RgbaImage::new(1, 1);
         expected_image.put_pixel(0, 0, RED_PIXEL);
 
-        assert_eq!(Some((1, expected_image)), actual);
+        assert_eq!(
+            Some((1, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
+        );
+    }
+
+    #[test]
+    fn test_heatmap() {
+        let left = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let mut right = RgbaImage::from_pixel(2, 1, Rgba([255, 255, 255, 255]));
+        right.put_pixel(1, 0, Rgba([4, 4, 4, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                style: cli::DiffStyle::Heatmap,
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_image = RgbaImage::new(2, 1);
+        expected_image.put_pixel(0, 0, Rgba([251, 240, 133, 255]));
+        expected_image.put_pixel(1, 0, Rgba([4, 1, 12, 255]));
+
+        assert_eq!(
+            Some((1, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
+        );
+    }
+
+    #[test]
+    fn test_heatmap_below_threshold() {
+        let left = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let mut right = left.clone();
+        right.put_pixel(1, 0, Rgba([4, 4, 4, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                style: cli::DiffStyle::Heatmap,
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_image = RgbaImage::new(2, 1);
+        expected_image.put_pixel(1, 0, Rgba([4, 1, 12, 255]));
+
+        assert_eq!(
+            Some((0, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
+        );
+    }
+
+    #[test]
+    fn test_diff_color_alt() {
+        let mut left = RgbaImage::from_pixel(2, 1, Rgba([128, 128, 128, 255]));
+        left.put_pixel(1, 0, Rgba([0, 0, 0, 255]));
+        let right = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                diff_color: Rgba([0, 255, 0, 255]),
+                diff_color_alt: Some(Rgba([0, 0, 255, 255])),
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_image = RgbaImage::new(2, 1);
+        expected_image.put_pixel(0, 0, Rgba([0, 0, 255, 255]));
+
+        assert_eq!(
+            Some((1, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
+        );
+    }
+
+    #[test]
+    fn test_block_out_color() {
+        let left = RgbaImage::from_pixel(2, 1, Rgba([128, 128, 128, 255]));
+        let right = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                block_out_areas: Some(HashSet::from([(1, 0)])),
+                block_out_color: Some(Rgba([0, 0, 255, 255])),
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_image = RgbaImage::new(2, 1);
+        expected_image.put_pixel(0, 0, RED_PIXEL);
+        expected_image.put_pixel(1, 0, Rgba([0, 0, 255, 255]));
+
+        assert_eq!(
+            Some((1, expected_image)),
+            actual.map(|output| (output.diffs, output.image))
+        );
+    }
+
+    #[test]
+    fn test_mask() {
+        let left = RgbaImage::from_pixel(3, 1, Rgba([0, 0, 0, 255]));
+        let mut right = RgbaImage::from_pixel(3, 1, Rgba([0, 0, 0, 255]));
+        right.put_pixel(0, 0, Rgba([255, 255, 255, 255]));
+        right.put_pixel(1, 0, Rgba([1, 1, 1, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                mask_output: Some(String::from("mask.png")),
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_mask = GrayImage::new(3, 1);
+        expected_mask.put_pixel(0, 0, MASK_DIFFERENT);
+
+        assert_eq!(Some(expected_mask), actual.and_then(|output| output.mask));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let left = RgbaImage::from_pixel(5, 4, Rgba([0, 0, 0, 255]));
+        let mut right = RgbaImage::from_pixel(5, 4, Rgba([0, 0, 0, 255]));
+        right.put_pixel(3, 1, Rgba([255, 255, 255, 255]));
+        right.put_pixel(1, 2, Rgba([255, 255, 255, 255]));
+        let actual = get_results(&left, &right, &RUN_PARAMS);
+
+        assert_eq!(
+            Some((1, 1, 3, 2)),
+            actual.and_then(|output| output.bounding_box)
+        );
+    }
+
+    #[test]
+    fn test_metrics() {
+        let left = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let mut right = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        right.put_pixel(0, 0, Rgba([10, 20, 30, 255]));
+        let (_, actual) = get_results_and_metrics(&left, &right, &RUN_PARAMS);
+
+        // per channel squared errors of 100, 400 and 900 over 2 pixels
+        let mse = (50.0 + 200.0 + 450.0) / 3.0;
+        assert_eq!(mse, actual.mse);
+        assert_eq!(10.0 * (255.0f64 * 255.0 / mse).log10(), actual.psnr);
+        assert_eq!(
+            [50.0f64.sqrt(), 200.0f64.sqrt(), 450.0f64.sqrt(), 0.0],
+            actual.rmse
+        );
+    }
+
+    #[test]
+    fn test_metrics_identical() {
+        let image = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let (output, actual) = get_results_and_metrics(&image, &image, &RUN_PARAMS);
+
+        assert_eq!(None, output);
+        assert_eq!(0.0, actual.mse);
+        assert_eq!(f64::INFINITY, actual.psnr);
+    }
+
+    #[test]
+    fn test_anti_aliased_count() {
+        let edge = |level| {
+            RgbaImage::from_fn(7, 7, |x, _| match x {
+                0..=2 => Rgba([0, 0, 0, 255]),
+                3 => Rgba([level, level, level, 255]),
+                _ => Rgba([255, 255, 255, 255]),
+            })
+        };
+        let actual = get_results(
+            &edge(128),
+            &edge(0),
+            &RunParams {
+                detect_anti_aliased_pixels: true,
+                ..RUN_PARAMS
+            },
+        )
+        .unwrap();
+
+        assert_eq!(0, actual.diffs);
+        assert_eq!(7, actual.anti_aliased);
+    }
+
+    #[test]
+    fn test_mask_anti_aliased() {
+        let edge = |level| {
+            RgbaImage::from_fn(7, 1, |x, _| match x {
+                0..=2 => Rgba([0, 0, 0, 255]),
+                3 => Rgba([level, level, level, 255]),
+                _ => Rgba([255, 255, 255, 255]),
+            })
+        };
+        let actual = get_results(
+            &edge(128),
+            &edge(0),
+            &RunParams {
+                detect_anti_aliased_pixels: true,
+                mask_output: Some(String::from("mask.png")),
+                ..RUN_PARAMS
+            },
+        );
+
+        let mut expected_mask = GrayImage::new(7, 1);
+        expected_mask.put_pixel(3, 0, MASK_ANTI_ALIASED);
+
+        assert_eq!(Some(expected_mask), actual.and_then(|output| output.mask));
+    }
+
+    #[test]
+    fn test_shift_tolerance() {
+        // a vertical black line moved by one column
+        let line = |column| {
+            RgbaImage::from_fn(7, 7, |x, _| match x == column {
+                true => Rgba([0, 0, 0, 255]),
+                false => Rgba([255, 255, 255, 255]),
+            })
+        };
+
+        let actual = get_results(&line(3), &line(4), &RUN_PARAMS).unwrap();
+        assert_eq!(14, actual.diffs);
+
+        let actual = get_results(
+            &line(3),
+            &line(4),
+            &RunParams {
+                shift_tolerance: Some(1),
+                ..RUN_PARAMS
+            },
+        );
+        assert!(actual.is_none());
+    }
+
+    #[test]
+    fn test_ignore_color() {
+        let mut left = RgbaImage::from_pixel(4, 1, Rgba([255, 255, 255, 255]));
+        // the same caret in both images, which is unchanged rather than blocked out
+        left.put_pixel(3, 0, Rgba([0, 120, 215, 255]));
+        let mut right = left.clone();
+        // a caret drawn in the right image only, and a real difference
+        right.put_pixel(1, 0, Rgba([0, 120, 215, 255]));
+        right.put_pixel(2, 0, Rgba([0, 0, 0, 255]));
+
+        let block_out_color = Rgba([255, 0, 255, 255]);
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                ignore_colors: vec![cli::IgnoreColor {
+                    color: Rgba([0, 120, 210, 255]),
+                    tolerance: 8,
+                }],
+                block_out_color: Some(block_out_color),
+                ..RUN_PARAMS
+            },
+        )
+        .unwrap();
+
+        assert_eq!(1, actual.diffs);
+        assert_eq!(block_out_color, *actual.image.get_pixel(1, 0));
+        assert_ne!(block_out_color, *actual.image.get_pixel(3, 0));
+    }
+
+    #[test]
+    fn test_luminance_only() {
+        // a red and a gray of the same luminance
+        let left = RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]));
+        let mut right = RgbaImage::from_pixel(2, 1, Rgba([76, 76, 76, 255]));
+        right.put_pixel(1, 0, Rgba([0, 0, 0, 255]));
+
+        let actual = get_results(&left, &right, &RUN_PARAMS).unwrap();
+        assert_eq!(2, actual.diffs);
+
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                luminance_only: true,
+                style: cli::DiffStyle::Luminance,
+                ..RUN_PARAMS
+            },
+        )
+        .unwrap();
+        assert_eq!(1, actual.diffs);
+        assert!(actual.image.get_pixel(1, 0)[0] < 128);
+
+        // changes below the threshold are drawn as well
+        let left = RgbaImage::from_pixel(1, 1, Rgba([128, 128, 128, 255]));
+        let right = RgbaImage::from_pixel(1, 1, Rgba([138, 138, 138, 255]));
+        let actual = get_results(
+            &left,
+            &right,
+            &RunParams {
+                style: cli::DiffStyle::Luminance,
+                ..RUN_PARAMS
+            },
+        )
+        .unwrap();
+        assert_eq!(0, actual.diffs);
+        assert!(actual.image.get_pixel(0, 0)[0] > 128);
+    }
+
+    #[test]
+    fn test_luminance_pixel() {
+        assert_eq!(Rgba([128, 128, 128, 255]), luminance_pixel(0.0));
+        assert_eq!(
+            Rgba([0, 0, 0, 255]),
+            luminance_pixel(-MAX_YIQ_POSSIBLE_DELTA)
+        );
+        assert_eq!(
+            Rgba([255, 255, 255, 255]),
+            luminance_pixel(MAX_YIQ_POSSIBLE_DELTA)
+        );
+    }
+
+    #[test]
+    fn test_linear_gradient() {
+        // a gray gradient and the same gradient lightened by a constant step
+        let gradient = |step: u8| {
+            RgbaImage::from_fn(240, 1, |x, _| {
+                let level = x as u8 + step;
+                Rgba([level, level, level, 255])
+            })
+        };
+        let params = RunParams {
+            threshold: 0.04,
+            ..RUN_PARAMS
+        };
+
+        // the step is the same everywhere in gamma-encoded values
+        let actual = get_results(&gradient(0), &gradient(10), &params);
+        assert!(actual.is_none());
+
+        // but a larger change of light in the highlights than in the shadows
+        let actual = get_results(
+            &gradient(0),
+            &gradient(10),
+            &RunParams {
+                linear: true,
+                ..params
+            },
+        )
+        .unwrap();
+        let (x, _, width, _) = actual.bounding_box.unwrap();
+        assert!(x > 128);
+        assert_eq!(240, x + width);
+    }
+
+    // a JPEG of the given dimensions with an EXIF orientation, i.e. as taken by a phone
+    fn jpeg_with_orientation(width: u32, height: u32, orientation: u8) -> Vec<u8> {
+        let mut jpeg = Vec::new();
+        image::RgbImage::from_pixel(width, height, image::Rgb([255, 0, 0]))
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+            .unwrap();
+
+        // a big-endian TIFF structure with a single entry, the orientation as a short
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        exif.extend([0, orientation, 0, 0, 0, 0, 0, 0].iter());
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend(((exif.len() + 2) as u16).to_be_bytes().iter());
+        segment.extend(exif);
+        // right after the start of image marker
+        jpeg.splice(2..2, segment);
+        jpeg
+    }
+
+    #[test]
+    fn test_orientation() {
+        use assert_fs::{fixture::FileWriteBin, NamedTempFile};
+
+        // rotated by 90 degrees clockwise when displayed
+        let file = NamedTempFile::new("orientation.jpg").unwrap();
+        file.write_binary(&jpeg_with_orientation(4, 2, 6)).unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let oriented = open_and_decode_image(path, "left", &cli::ColorSpace::Srgb, false);
+        let stored = open_and_decode_image(path, "left", &cli::ColorSpace::Srgb, true);
+
+        assert_eq!((2, 4), oriented.unwrap().dimensions());
+        assert_eq!((4, 2), stored.unwrap().dimensions());
+
+        file.close().unwrap();
     }
 }
@@ -0,0 +1,117 @@
+use super::{cli, diff, histogram};
+use anyhow::Result;
+use colored::*;
+use image::RgbaImage;
+
+// what git passes in place of the missing side of an added or deleted file
+const NULL_PATHS: [&str; 2] = ["/dev/null", "nul"];
+
+// 64-bit FNV-1a, stable across platforms and releases unlike the hasher of the standard library
+fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+// hash of the decoded pixels, so that re-encoding an unchanged image keeps it
+fn hash(image: &RgbaImage) -> u64 {
+    let (width, height) = image.dimensions();
+
+    fnv1a(
+        width
+            .to_le_bytes()
+            .iter()
+            .chain(height.to_le_bytes().iter())
+            .chain(image.as_raw().iter())
+            .copied(),
+    )
+}
+
+fn summary(image: &RgbaImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut lines = vec![
+        format!("dimensions: {width}x{height}"),
+        format!("hash: {:016x}", hash(image)),
+        "dominant colors:".to_owned(),
+    ];
+
+    for ([r, g, b, a], percentage) in histogram::dominant_colors(image) {
+        lines.push(format!("  #{r:02x}{g:02x}{b:02x}{a:02x} {percentage:.1}%"));
+    }
+
+    lines.join("\n")
+}
+
+// prints a textual summary of the image, for `git diff` through a textconv driver
+pub fn textconv(path: &str, color_space: &cli::ColorSpace, ignore_orientation: bool) -> Result<()> {
+    let image = diff::open_and_decode_image(path, "input", color_space, ignore_orientation)?;
+    println!("{}", summary(&image));
+    Ok(())
+}
+
+// compares git's $LOCAL and $REMOTE, `merged` is the path of the file in the work tree
+pub fn difftool(params: &diff::RunParams, merged: Option<&str>) -> Result<Option<i32>> {
+    let name = merged.unwrap_or(params.right);
+
+    if NULL_PATHS.contains(&params.left) {
+        println!("\"{}\" added", name.magenta());
+        return Ok(None);
+    }
+
+    if NULL_PATHS.contains(&params.right) {
+        println!("\"{}\" deleted", name.magenta());
+        return Ok(None);
+    }
+
+    let result = diff::run(params)?;
+
+    match result {
+        Some(0) | None => println!("\"{}\" {}", name.magenta(), "no differences".green()),
+        Some(diffs) => println!(
+            "\"{}\" {} different pixels, see \"{}\"",
+            name.magenta(),
+            diffs.to_string().red(),
+            params.output.magenta()
+        ),
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, fnv1a(Vec::new()));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, fnv1a(b"a".iter().copied()));
+    }
+
+    #[test]
+    fn test_summary() {
+        let mut image = RgbaImage::from_pixel(4, 1, Rgba([255, 255, 255, 255]));
+        image.put_pixel(0, 0, Rgba([255, 0, 0, 255]));
+
+        let actual = summary(&image);
+        let expected = [
+            "dimensions: 4x1",
+            &format!("hash: {:016x}", hash(&image)),
+            "dominant colors:",
+            "  #ffffffff 75.0%",
+            "  #ff0000ff 25.0%",
+        ]
+        .join("\n");
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_hash_depends_on_dimensions() {
+        let wide = RgbaImage::new(4, 1);
+        let tall = RgbaImage::new(1, 4);
+
+        assert_ne!(hash(&wide), hash(&tall));
+    }
+}
@@ -0,0 +1,169 @@
+use super::{cli, diff};
+use anyhow::Result;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const CHANNELS: [&str; 3] = ["red", "green", "blue"];
+const DOMINANT_COLORS: usize = 5;
+
+// the share of pixels at each level of the red, green and blue channel
+fn histograms(image: &RgbaImage) -> [[f64; 256]; 3] {
+    let mut histograms = [[0.0; 256]; 3];
+
+    for pixel in image.pixels() {
+        for (histogram, level) in histograms.iter_mut().zip(pixel.0.iter()) {
+            histogram[usize::from(*level)] += 1.0;
+        }
+    }
+
+    let total = image.pixels().len().max(1) as f64;
+    for level in histograms.iter_mut().flatten() {
+        *level /= total;
+    }
+
+    histograms
+}
+
+// symmetric chi-square distance, from 0 for equal histograms to 2 for disjoint ones
+fn chi_square(left: &[f64; 256], right: &[f64; 256]) -> f64 {
+    left.iter()
+        .zip(right.iter())
+        .filter(|(l, r)| *l + *r > 0.0)
+        .map(|(l, r)| (l - r).powi(2) / (l + r))
+        .sum()
+}
+
+// Bhattacharyya distance in the Hellinger form, from 0 for equal histograms to 1 for disjoint ones
+fn bhattacharyya(left: &[f64; 256], right: &[f64; 256]) -> f64 {
+    let coefficient: f64 = left
+        .iter()
+        .zip(right.iter())
+        .map(|(l, r)| (l * r).sqrt())
+        .sum();
+
+    (1.0 - coefficient).max(0.0).sqrt()
+}
+
+fn mean(histogram: &[f64; 256]) -> f64 {
+    histogram
+        .iter()
+        .enumerate()
+        .map(|(level, share)| level as f64 * share)
+        .sum()
+}
+
+// the most common colors, with pixels grouped by the 4 most significant bits of each channel and
+// each group represented by the average of its colors
+pub(crate) fn dominant_colors(image: &RgbaImage) -> Vec<([u8; 4], f32)> {
+    let mut groups: HashMap<[u8; 4], (u64, [u64; 4])> = HashMap::new();
+
+    for pixel in image.pixels() {
+        let key = [pixel[0] >> 4, pixel[1] >> 4, pixel[2] >> 4, pixel[3] >> 4];
+        let (count, sums) = groups.entry(key).or_insert((0, [0; 4]));
+        *count += 1;
+        for (sum, channel) in sums.iter_mut().zip(pixel.0.iter()) {
+            *sum += u64::from(*channel);
+        }
+    }
+
+    let mut groups: Vec<(u64, [u64; 4])> = groups.into_values().collect();
+    groups.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+    let total = image.pixels().len() as f32;
+
+    groups
+        .into_iter()
+        .take(DOMINANT_COLORS)
+        .map(|(count, sums)| {
+            let mut color = [0; 4];
+            for (channel, sum) in color.iter_mut().zip(sums.iter()) {
+                *channel = (sum / count) as u8;
+            }
+            (color, count as f32 / total * 100.0)
+        })
+        .collect()
+}
+
+// compares the color distributions of the images, which reveals global shifts such as a wrong
+// color profile that counting different pixels obscures
+pub fn report(left: &RgbaImage, right: &RgbaImage) -> String {
+    let (left_histograms, right_histograms) = (histograms(left), histograms(right));
+    let mut report = String::new();
+
+    for (name, (l, r)) in CHANNELS
+        .iter()
+        .zip(left_histograms.iter().zip(right_histograms.iter()))
+    {
+        let _ = writeln!(
+            report,
+            "{}: chi-square {:.4}, bhattacharyya {:.4}, mean {:+.2}",
+            name,
+            chi_square(l, r),
+            bhattacharyya(l, r),
+            mean(r) - mean(l)
+        );
+    }
+
+    report.push_str("dominant colors:");
+    let (left_colors, right_colors) = (dominant_colors(left), dominant_colors(right));
+    let format = |color: Option<&([u8; 4], f32)>| match color {
+        Some(([r, g, b, a], percentage)) => {
+            format!("#{r:02x}{g:02x}{b:02x}{a:02x} {percentage:.1}%")
+        }
+        None => "-".to_owned(),
+    };
+
+    for i in 0..left_colors.len().max(right_colors.len()) {
+        let _ = write!(
+            report,
+            "\n  {} -> {}",
+            format(left_colors.get(i)),
+            format(right_colors.get(i))
+        );
+    }
+
+    report
+}
+
+// prints the histogram report of two images, for `dify stats`
+pub fn stats(
+    left: &str,
+    right: &str,
+    color_space: &cli::ColorSpace,
+    ignore_orientation: bool,
+) -> Result<()> {
+    let (left_image, right_image) =
+        diff::open_and_decode_images(left, right, color_space, ignore_orientation)?;
+
+    println!("{}", report(&left_image, &right_image));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_distances() {
+        let black = histograms(&RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255])));
+        let white = histograms(&RgbaImage::from_pixel(2, 2, Rgba([255, 255, 255, 255])));
+
+        assert_eq!(0.0, chi_square(&black[0], &black[0]));
+        assert_eq!(0.0, bhattacharyya(&black[0], &black[0]));
+        assert_eq!(2.0, chi_square(&black[0], &white[0]));
+        assert_eq!(1.0, bhattacharyya(&black[0], &white[0]));
+    }
+
+    #[test]
+    fn test_report() {
+        let left = RgbaImage::from_pixel(2, 2, Rgba([100, 100, 100, 255]));
+        let right = RgbaImage::from_pixel(2, 2, Rgba([110, 100, 100, 255]));
+        let actual = report(&left, &right);
+
+        assert!(actual.starts_with("red: chi-square 2.0000, bhattacharyya 1.0000, mean +10.00\n"));
+        assert!(actual.contains("green: chi-square 0.0000, bhattacharyya 0.0000, mean +0.00\n"));
+        assert!(actual.ends_with("dominant colors:\n  #646464ff 100.0% -> #6e6464ff 100.0%"));
+    }
+}
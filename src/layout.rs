@@ -0,0 +1,135 @@
+use image::{imageops, GenericImageView, Rgba, RgbaImage};
+
+const GAP: u32 = 10;
+const BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const GLYPH_WIDTH: u32 = 5;
+const GLYPH_HEIGHT: u32 = 7;
+
+// 5x7 bitmaps of the letters used by the labels, one row per byte, most significant bit first
+fn glyph(letter: char) -> [u8; 7] {
+    match letter {
+        'D' => [0x1e, 0x11, 0x11, 0x11, 0x11, 0x11, 0x1e],
+        'E' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x1f],
+        'F' => [0x1f, 0x10, 0x10, 0x1e, 0x10, 0x10, 0x10],
+        'G' => [0x0e, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0f],
+        'H' => [0x11, 0x11, 0x11, 0x1f, 0x11, 0x11, 0x11],
+        'I' => [0x0e, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        'L' => [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1f],
+        'R' => [0x1e, 0x11, 0x11, 0x1e, 0x14, 0x12, 0x11],
+        'T' => [0x1f, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04],
+        _ => [0; 7],
+    }
+}
+
+fn draw_label(canvas: &mut RgbaImage, text: &str, x: u32, y: u32, scale: u32) {
+    for (n, letter) in text.chars().enumerate() {
+        let left = x + n as u32 * (GLYPH_WIDTH + 1) * scale;
+
+        for (row, bits) in glyph(letter).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - column)) == 0 {
+                    continue;
+                }
+
+                for dx in 0..scale {
+                    for dy in 0..scale {
+                        let (px, py) = (left + column * scale + dx, y + row as u32 * scale + dy);
+                        if canvas.in_bounds(px, py) {
+                            canvas.put_pixel(px, py, LABEL_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// places left, right and diff next to each other, wide images are stacked vertically instead;
+// every panel is padded to the largest width and height so that mismatched dimensions line up
+pub fn side_by_side(
+    left: &RgbaImage,
+    right: &RgbaImage,
+    diff: &RgbaImage,
+    labels: bool,
+) -> RgbaImage {
+    let panels = [(left, "LEFT"), (right, "RIGHT"), (diff, "DIFF")];
+    let width = panels
+        .iter()
+        .map(|(image, _)| image.width())
+        .max()
+        .unwrap_or(0);
+    let height = panels
+        .iter()
+        .map(|(image, _)| image.height())
+        .max()
+        .unwrap_or(0);
+    let scale = (width / 200).clamp(1, 8);
+    let label_height = if labels {
+        (GLYPH_HEIGHT + 4) * scale
+    } else {
+        0
+    };
+    let (cell_width, cell_height) = (width, label_height + height);
+    let vertical = width > height;
+
+    let mut canvas = if vertical {
+        RgbaImage::from_pixel(cell_width, cell_height * 3 + GAP * 2, BACKGROUND)
+    } else {
+        RgbaImage::from_pixel(cell_width * 3 + GAP * 2, cell_height, BACKGROUND)
+    };
+
+    for (n, (image, label)) in panels.iter().enumerate() {
+        let n = n as u32;
+        let (x, y) = if vertical {
+            (0, n * (cell_height + GAP))
+        } else {
+            (n * (cell_width + GAP), 0)
+        };
+
+        if labels {
+            draw_label(&mut canvas, label, x + 2 * scale, y + 2 * scale, scale);
+        }
+
+        imageops::overlay(&mut canvas, *image, x.into(), (y + label_height).into());
+    }
+
+    canvas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_side_by_side() {
+        let left = RgbaImage::from_pixel(2, 3, Rgba([1, 1, 1, 255]));
+        let right = RgbaImage::from_pixel(1, 1, Rgba([2, 2, 2, 255]));
+        let diff = RgbaImage::from_pixel(2, 3, Rgba([3, 3, 3, 255]));
+        let actual = side_by_side(&left, &right, &diff, false);
+
+        assert_eq!((2 * 3 + GAP * 2, 3), actual.dimensions());
+        assert_eq!(&Rgba([1, 1, 1, 255]), actual.get_pixel(1, 2));
+        assert_eq!(&Rgba([2, 2, 2, 255]), actual.get_pixel(2 + GAP, 0));
+        assert_eq!(&BACKGROUND, actual.get_pixel(2 + GAP, 1));
+        assert_eq!(&Rgba([3, 3, 3, 255]), actual.get_pixel(4 + GAP * 2, 2));
+    }
+
+    #[test]
+    fn test_side_by_side_wide() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([1, 1, 1, 255]));
+        let actual = side_by_side(&image, &image, &image, false);
+
+        assert_eq!((3, 2 * 3 + GAP * 2), actual.dimensions());
+    }
+
+    #[test]
+    fn test_side_by_side_labels() {
+        let image = RgbaImage::from_pixel(2, 3, Rgba([1, 1, 1, 255]));
+        let actual = side_by_side(&image, &image, &image, true);
+
+        assert_eq!((2 * 3 + GAP * 2, 3 + GLYPH_HEIGHT + 4), actual.dimensions());
+        // top-left corner of the "L"
+        assert_eq!(&LABEL_COLOR, actual.get_pixel(2, 2));
+    }
+}
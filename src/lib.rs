@@ -1,41 +1,76 @@
+mod animation;
 pub mod cli;
+mod color;
+mod colormap;
+mod config;
 pub mod diff;
+pub mod git;
+pub mod histogram;
+mod layout;
+pub mod sequence;
+pub mod serve;
+mod text;
+pub mod watch;
 mod yiq;
 
-use crate::yiq::Yiq;
-use image::{Pixel, RgbaImage};
+use crate::yiq::Measure;
+use image::{GenericImageView, RgbaImage};
 use std::cmp;
+use std::convert::TryFrom;
 
-fn get_diagonal_neighbours(x1: u32, y1: u32, width: u32, height: u32) -> ((u32, u32), (u32, u32)) {
+// the neighbourhood within the radius of the pixel, cut off at the borders of the image
+fn get_neighbourhood(
+    x1: u32,
+    y1: u32,
+    radius: u32,
+    width: u32,
+    height: u32,
+) -> ((u32, u32), (u32, u32)) {
     // (x0, y0)
     //          (x1, y1)
     //                   (x2, y2)
-    let (x0, y0) = (x1.saturating_sub(1), y1.saturating_sub(1));
+    let (x0, y0) = (x1.saturating_sub(radius), y1.saturating_sub(radius));
     let (x2, y2) = (
-        cmp::min(x1.saturating_add(1), width.saturating_sub(1)),
-        cmp::min(y1.saturating_add(1), height.saturating_sub(1)),
+        cmp::min(x1.saturating_add(radius), width.saturating_sub(1)),
+        cmp::min(y1.saturating_add(radius), height.saturating_sub(1)),
     );
 
     ((x0, y0), (x2, y2))
 }
 
-fn on_the_edge(top_left: (u32, u32), center: (u32, u32), bottom_right: (u32, u32)) -> bool {
-    center.0 == top_left.0
-        || center.0 == bottom_right.0
-        || center.1 == top_left.1
-        || center.1 == bottom_right.1
-}
+// the number of similar neighbours which makes a pixel part of a flat area; the neighbourhood of
+// pixels close to the borders is cut off, so proportionally fewer are required there, which for
+// a radius of 1 and 3 siblings matches pixelmatch; computed in u128 so that no radius or number
+// of siblings can overflow
+fn required_siblings(
+    top_left: (u32, u32),
+    bottom_right: (u32, u32),
+    options: &cli::AntiAliasing,
+) -> u32 {
+    let full = (2 * u128::from(options.radius) + 1).pow(2) - 1;
+    let available = u128::from(bottom_right.0 - top_left.0 + 1)
+        * u128::from(bottom_right.1 - top_left.1 + 1)
+        - 1;
 
-fn has_many_siblings(image: &RgbaImage, x1: u32, y1: u32, width: u32, height: u32) -> bool {
-    let ((x0, y0), (x2, y2)) = get_diagonal_neighbours(x1, y1, width, height);
+    u32::try_from((u128::from(options.siblings) * available).div_ceil(full)).unwrap_or(u32::MAX)
+}
 
-    let mut zeros: u32 = if on_the_edge((x0, y0), (x1, y1), (x2, y2)) {
-        0
-    } else {
-        1
-    };
+fn has_many_siblings(
+    image: &RgbaImage,
+    x1: u32,
+    y1: u32,
+    background: &cli::Background,
+    options: &cli::AntiAliasing,
+    measure: &Measure,
+) -> bool {
+    let (width, height) = image.dimensions();
+    let ((x0, y0), (x2, y2)) = get_neighbourhood(x1, y1, options.radius, width, height);
+    let required = required_siblings((x0, y0), (x2, y2), options);
 
-    let center = &image.get_pixel(x1, y1);
+    let center = image.get_pixel(x1, y1);
+    // luminance is only needed to tell whether different pixels are within the tolerance
+    let center_y = (options.tolerance > 0.0).then(|| measure.yiq(center, background.at(x1, y1)));
+    let mut zeros: u32 = 0;
 
     for x in x0..=x2 {
         for y in y0..=y2 {
@@ -43,11 +78,18 @@ fn has_many_siblings(image: &RgbaImage, x1: u32, y1: u32, width: u32, height: u3
                 continue;
             }
 
-            if center == &image.get_pixel(x, y) {
+            let pixel = image.get_pixel(x, y);
+            let similar = center == pixel
+                || center_y.as_ref().is_some_and(|center_y| {
+                    let neighbor = measure.yiq(pixel, background.at(x, y));
+                    center_y.delta_y(&neighbor).abs() <= options.tolerance
+                });
+
+            if similar {
                 zeros += 1;
             }
 
-            if zeros > 2 {
+            if zeros >= required {
                 return true;
             }
         }
@@ -60,18 +102,16 @@ pub fn antialiased(
     left: &RgbaImage,
     x1: u32,
     y1: u32,
-    width: u32,
-    height: u32,
     right: &RgbaImage,
+    background: &cli::Background,
+    options: &cli::AntiAliasing,
+    measure: &Measure,
 ) -> bool {
-    let ((x0, y0), (x2, y2)) = get_diagonal_neighbours(x1, y1, width, height);
-
-    let mut zeros: u32 = if on_the_edge((x0, y0), (x1, y1), (x2, y2)) {
-        1
-    } else {
-        0
-    };
+    let (width, height) = left.dimensions();
+    let ((x0, y0), (x2, y2)) = get_neighbourhood(x1, y1, options.radius, width, height);
+    let required = required_siblings((x0, y0), (x2, y2), options);
 
+    let mut zeros: u32 = 0;
     let mut min: f32 = 0.0;
     let mut max: f32 = 0.0;
     let mut min_x: u32 = 0;
@@ -79,7 +119,7 @@ pub fn antialiased(
     let mut max_x: u32 = 0;
     let mut max_y: u32 = 0;
 
-    let center = &left.get_pixel(x1, y1).to_rgb();
+    let center = measure.yiq(left.get_pixel(x1, y1), background.at(x1, y1));
 
     for x in x0..=x2 {
         for y in y0..=y2 {
@@ -87,12 +127,12 @@ pub fn antialiased(
                 continue;
             }
 
-            let neighbor = left.get_pixel(x, y).to_rgb();
-            let delta = Yiq::delta_y(center, &neighbor);
+            let neighbor = measure.yiq(left.get_pixel(x, y), background.at(x, y));
+            let delta = center.delta_y(&neighbor);
 
-            if delta == 0.0 {
+            if delta.abs() <= options.tolerance {
                 zeros += 1;
-                if zeros > 2 {
+                if zeros >= required {
                     return false;
                 }
             } else if delta < min {
@@ -111,19 +151,200 @@ pub fn antialiased(
         return false;
     }
 
-    (has_many_siblings(left, min_x, min_y, width, height)
-        && has_many_siblings(right, min_x, min_y, width, height))
-        || (has_many_siblings(left, max_x, max_y, width, height)
-            && has_many_siblings(right, max_x, max_y, width, height))
+    let has_many_siblings = |x, y| {
+        has_many_siblings(left, x, y, background, options, measure)
+            && right.in_bounds(x, y)
+            && has_many_siblings(right, x, y, background, options, measure)
+    };
+
+    has_many_siblings(min_x, min_y) || has_many_siblings(max_x, max_y)
+}
+
+// the smallest delta between the left pixel and the right pixels within the radius around it, so
+// that content moved by a few pixels still matches when it is within the threshold; the right
+// pixel has to be found among the left pixels around it as well, or else content appearing next to
+// a similar pixel would pass for moved, and the larger of both deltas is returned
+pub fn shifted_match(
+    left: &RgbaImage,
+    x1: u32,
+    y1: u32,
+    right: &RgbaImage,
+    radius: u32,
+    background: &cli::Background,
+    measure: &Measure,
+) -> Option<f32> {
+    // both images are looked at around the pixel, which are of different sizes with -i
+    let width = left.width().min(right.width());
+    let height = left.height().min(right.height());
+    let ((x0, y0), (x2, y2)) = get_neighbourhood(x1, y1, radius, width, height);
+    let neighbourhood = || (x0..=x2).flat_map(|x| (y0..=y2).map(move |y| (x, y)));
+    let yiq = |image: &RgbaImage, x, y| measure.yiq(image.get_pixel(x, y), background.at(x, y));
+    let (left_center, right_center) = (yiq(left, x1, y1), yiq(right, x1, y1));
+
+    let forward = neighbourhood()
+        .map(|(x, y)| measure.delta(&left_center, &yiq(right, x, y)))
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))?;
+    let reverse = neighbourhood()
+        .map(|(x, y)| measure.delta(&yiq(left, x, y), &right_center))
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))?;
+
+    Some(if forward.abs() >= reverse.abs() {
+        forward
+    } else {
+        reverse
+    })
+}
+
+pub fn blend(color: f32, alpha: f32, background: f32) -> f32 {
+    background + (color - background) * alpha
 }
 
 pub fn blend_semi_transparent_white(color: f32, alpha: f32) -> f32 {
-    255.0 + (color - 255.0) * alpha
+    blend(color, alpha, 255.0)
 }
 
 #[cfg(test)]
 mod tests {
-    use super::blend_semi_transparent_white;
+    use super::{
+        antialiased, blend, blend_semi_transparent_white, required_siblings, shifted_match,
+    };
+    use crate::cli::{AntiAliasing, Background};
+    use crate::yiq::Measure;
+    use image::{Rgba, RgbaImage};
+
+    // a vertical edge with the given gray level in each column
+    fn columns(levels: &[u8]) -> RgbaImage {
+        RgbaImage::from_fn(levels.len() as u32, 7, |x, _| {
+            let level = levels[x as usize];
+            Rgba([level, level, level, 255])
+        })
+    }
+
+    #[test]
+    fn test_required_siblings() {
+        let options = AntiAliasing::default();
+        assert_eq!(3, required_siblings((0, 0), (2, 2), &options));
+        assert_eq!(2, required_siblings((0, 0), (1, 2), &options));
+        assert_eq!(2, required_siblings((0, 0), (1, 1), &options));
+
+        let options = AntiAliasing {
+            radius: 2,
+            ..AntiAliasing::default()
+        };
+        assert_eq!(3, required_siblings((0, 0), (4, 4), &options));
+        assert_eq!(1, required_siblings((0, 0), (2, 2), &options));
+
+        let options = AntiAliasing {
+            radius: 70000,
+            siblings: u32::MAX,
+            ..AntiAliasing::default()
+        };
+        assert_eq!(22, required_siblings((0, 0), (9, 9), &options));
+        assert_eq!(
+            u32::MAX,
+            required_siblings((0, 0), (140_000, 140_000), &options)
+        );
+    }
+
+    #[test]
+    fn test_antialiased_edge() {
+        let options = AntiAliasing::default();
+        let left = columns(&[0, 0, 0, 128, 255, 255, 255]);
+        let right = columns(&[0, 0, 0, 0, 255, 255, 255]);
+
+        assert!(antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure::default()
+        ));
+    }
+
+    #[test]
+    fn test_antialiased_flat_area() {
+        let options = AntiAliasing::default();
+        let mut left = columns(&[0, 0, 0, 0, 0, 0, 0]);
+        left.put_pixel(3, 3, Rgba([128, 128, 128, 255]));
+        let right = columns(&[0, 0, 0, 0, 0, 0, 0]);
+
+        assert!(!antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure::default()
+        ));
+    }
+
+    #[test]
+    fn test_antialiased_tolerance() {
+        // the areas on both sides of the edge are slightly noisy, as with subpixel rendering
+        let left = columns(&[0, 3, 6, 128, 255, 252, 249]);
+        let right = columns(&[0, 3, 6, 6, 255, 252, 249]);
+
+        let options = AntiAliasing::default();
+        assert!(!antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure::default()
+        ));
+
+        let options = AntiAliasing {
+            tolerance: 8.0,
+            ..AntiAliasing::default()
+        };
+        assert!(antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure::default()
+        ));
+    }
+
+    #[test]
+    fn test_antialiased_linear() {
+        // noise in the dark area is much smaller in linear light, unlike in the light area
+        let left = columns(&[0, 3, 6, 128, 255, 245, 235]);
+        let right = columns(&[0, 3, 6, 6, 255, 245, 235]);
+        let options = AntiAliasing {
+            tolerance: 1.0,
+            ..AntiAliasing::default()
+        };
+
+        assert!(!antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure::default()
+        ));
+        assert!(antialiased(
+            &left,
+            3,
+            3,
+            &right,
+            &Background::White,
+            &options,
+            &Measure {
+                linear: true,
+                ..Measure::default()
+            }
+        ));
+    }
 
     #[test]
     fn test_blend_semi_transparent_white() {
@@ -151,4 +372,52 @@ mod tests {
         assert_eq!(25.5, blend_semi_transparent_white(0.0, 0.9));
         assert_eq!(0.0, blend_semi_transparent_white(0.0, 1.0));
     }
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(0.0, blend(255.0, 0.0, 0.0));
+        assert_eq!(127.5, blend(255.0, 0.5, 0.0));
+        assert_eq!(255.0, blend(255.0, 1.0, 0.0));
+        assert_eq!(204.0, blend(0.0, 0.0, 204.0));
+        assert_eq!(102.0, blend(0.0, 0.5, 204.0));
+    }
+
+    #[test]
+    fn test_shifted_match() {
+        // the delta of black to white
+        const BLACK_TO_WHITE: f32 = 32857.133;
+
+        let shifted_match = |left: &RgbaImage, right: &RgbaImage, x, radius| {
+            shifted_match(
+                left,
+                x,
+                3,
+                right,
+                radius,
+                &Background::White,
+                &Measure::default(),
+            )
+        };
+
+        let left = columns(&[255, 255, 0, 255, 255, 255, 255]);
+        let right = columns(&[255, 255, 255, 0, 255, 255, 255]);
+        assert_eq!(Some(0.0), shifted_match(&left, &right, 2, 1));
+        assert_eq!(Some(0.0), shifted_match(&left, &right, 3, 1));
+        assert_eq!(Some(BLACK_TO_WHITE), shifted_match(&left, &right, 2, 0));
+
+        let right = columns(&[255, 255, 255, 255, 0, 255, 255]);
+        assert_eq!(Some(BLACK_TO_WHITE), shifted_match(&left, &right, 2, 1));
+        assert_eq!(Some(0.0), shifted_match(&left, &right, 2, 2));
+
+        // a pixel appearing next to a similar one has not moved from anywhere in the left image
+        let left = columns(&[255, 255, 255, 255, 255, 255, 255]);
+        let right = columns(&[255, 255, 255, 0, 255, 255, 255]);
+        assert_eq!(Some(-BLACK_TO_WHITE), shifted_match(&left, &right, 3, 1));
+
+        // the neighbourhood stays within the smaller image
+        let left = RgbaImage::from_pixel(5, 5, Rgba([255, 255, 255, 255]));
+        let right = RgbaImage::from_pixel(7, 7, Rgba([255, 255, 255, 255]));
+        assert_eq!(Some(0.0), shifted_match(&left, &right, 4, 1));
+        assert_eq!(Some(0.0), shifted_match(&right, &left, 4, 1));
+    }
 }
@@ -1,5 +1,8 @@
 use anyhow::Result;
-use dify::{cli::Cli, diff};
+use dify::{
+    cli::{Cli, Subcommand},
+    diff, git, histogram, sequence, serve, watch,
+};
 
 fn main() -> Result<()> {
     let cli = Cli::new()?;
@@ -14,16 +17,55 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if let Some(Subcommand::Textconv) = cli.get_subcommand() {
+        return git::textconv(
+            cli.get_image_path_of_textconv()?,
+            &cli.get_color_space()?,
+            cli.ignore_orientation(),
+        );
+    }
+
+    if let Some(Subcommand::Stats) = cli.get_subcommand() {
+        let (left, right) = cli.get_image_paths_of_left_right_diff()?;
+        return histogram::stats(
+            left,
+            right,
+            &cli.get_color_space()?,
+            cli.ignore_orientation(),
+        );
+    }
+
     let (left, right) = cli.get_image_paths_of_left_right_diff()?;
     let output = &cli.get_output_image_path();
     let output_image_base = cli.copy_specific_image_to_output_as_base()?;
     let do_not_check_dimensions = cli.do_not_check_dimensions();
     let threshold = cli.get_threshold()?;
     let detect_anti_aliased_pixels = cli.detect_anti_aliased_pixels();
+    let anti_aliasing = cli.get_anti_aliasing()?;
     let blend_factor_of_unchanged_pixels = cli.blend_factor_of_unchanged_pixels()?;
-    let block_out_areas = cli.get_block_out_area();
+    let block_out_areas = cli.get_block_out_area()?;
+    let background = cli.get_background()?;
+    let style = cli.get_diff_style()?;
+    let diff_color = cli.get_diff_color()?;
+    let diff_color_alt = cli.get_diff_color_alt()?;
+    let anti_aliased_color = cli.get_anti_aliased_color()?;
+    let block_out_color = cli.get_block_out_color()?;
+    let layout = cli.get_layout()?;
+    let flicker = cli.get_flicker()?;
+    let mask_output = cli.get_mask_output_path();
+    let crop_to_diff = cli.crop_to_diff()?;
+    let print_metrics = cli.print_metrics();
+    let anti_aliased_budget = cli.get_anti_aliased_budget()?;
+    let text_tolerance = cli.get_text_tolerance()?;
+    let shift_tolerance = cli.get_shift_tolerance()?;
+    let ignore_colors = cli.get_ignore_colors()?;
+    let luminance_only = cli.luminance_only();
+    let linear = cli.linear();
+    let ignore_orientation = cli.ignore_orientation();
+    let metric = cli.get_metric()?;
+    let color_space = cli.get_color_space()?;
 
-    diff::run(&diff::RunParams {
+    let params = diff::RunParams {
         left,
         right,
         output,
@@ -31,10 +73,40 @@ fn main() -> Result<()> {
         output_image_base,
         do_not_check_dimensions,
         detect_anti_aliased_pixels,
+        anti_aliasing,
         blend_factor_of_unchanged_pixels,
         block_out_areas,
-    })
-    .map(|code| {
+        background,
+        style,
+        diff_color,
+        diff_color_alt,
+        anti_aliased_color,
+        block_out_color,
+        layout,
+        flicker,
+        mask_output,
+        crop_to_diff,
+        print_metrics,
+        anti_aliased_budget,
+        text_tolerance,
+        shift_tolerance,
+        ignore_colors,
+        luminance_only,
+        linear,
+        ignore_orientation,
+        metric,
+        color_space,
+    };
+
+    let result = match cli.get_subcommand() {
+        Some(Subcommand::Serve) => return serve::run(&params, cli.get_port()?),
+        Some(Subcommand::GitDifftool) => git::difftool(&params, cli.get_merged_path()),
+        _ if cli.sequence() => sequence::run(&params),
+        _ if cli.watch() => return watch::run(&params),
+        _ => diff::run(&params),
+    };
+
+    result.map(|code| {
         if let Some(code) = code {
             std::process::exit(code)
         }
@@ -0,0 +1,334 @@
+use super::diff;
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use image::{ImageFormat, RgbaImage};
+use rayon::prelude::*;
+use std::convert::TryFrom;
+use std::path::Path;
+
+// width of the bar of the frame with the most different pixels in the difference curve
+const CURVE_WIDTH: i64 = 40;
+
+// a printf-like pattern of frame paths with a single `%d`, `%Nd` or `%0Nd` conversion for the
+// frame number, e.g. `frame_%04d.png`; `%%` stands for a literal `%`
+#[derive(Debug, PartialEq)]
+struct Pattern {
+    prefix: String,
+    suffix: String,
+    width: usize,
+    zero_padded: bool,
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let mut parts = vec![String::new()];
+        let mut conversion = None;
+        let mut chars = pattern.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != '%' || chars.next_if_eq(&'%').is_some() {
+                parts.last_mut()?.push(c);
+                continue;
+            }
+
+            let zero_padded = chars.next_if_eq(&'0').is_some();
+            let mut width = String::new();
+            while let Some(digit) = chars.next_if(|c| c.is_ascii_digit()) {
+                width.push(digit);
+            }
+
+            if chars.next() != Some('d') || conversion.is_some() {
+                return None;
+            }
+
+            conversion = Some((zero_padded, width.parse().unwrap_or(0)));
+            parts.push(String::new());
+        }
+
+        let (zero_padded, width) = conversion?;
+        let suffix = parts.pop()?;
+        let prefix = parts.pop()?;
+
+        Some(Self {
+            prefix,
+            suffix,
+            width,
+            zero_padded,
+        })
+    }
+
+    fn format(&self, number: u32) -> String {
+        let number = match self.zero_padded {
+            true => format!("{:0width$}", number, width = self.width),
+            false => format!("{:width$}", number, width = self.width),
+        };
+
+        format!("{}{}{}", self.prefix, number, self.suffix)
+    }
+
+    fn exists(&self, number: u32) -> bool {
+        Path::new(&self.format(number)).is_file()
+    }
+}
+
+enum FrameResult {
+    Compared {
+        diffs: i32,
+        anti_aliased: i32,
+        metrics: diff::Metrics,
+    },
+    Missing(i32),
+}
+
+impl FrameResult {
+    fn diffs(&self) -> i32 {
+        match self {
+            Self::Compared { diffs, .. } | Self::Missing(diffs) => *diffs,
+        }
+    }
+}
+
+fn parse_pattern(pattern: &str, which: &str) -> Result<Pattern> {
+    Pattern::parse(pattern).ok_or_else(|| {
+        anyhow!(format!(
+            "the {} argument \"{}\" is not a frame pattern, e.g. {}",
+            which.magenta(),
+            pattern.magenta(),
+            "frame_%04d.png".magenta()
+        )
+        .red())
+    })
+}
+
+fn write_diff(path: &str, image: &RgbaImage) -> Result<()> {
+    image
+        .save_with_format(path, ImageFormat::Png)
+        .with_context(|| format!("failed to write diff image \"{}\"", path.magenta()).red())
+}
+
+fn compare_frame(
+    params: &diff::RunParams,
+    left: &Pattern,
+    right: &Pattern,
+    output: Option<&Pattern>,
+    number: u32,
+) -> Result<FrameResult> {
+    let (left_path, right_path) = (left.format(number), right.format(number));
+
+    match (left.exists(number), right.exists(number)) {
+        (true, true) => {
+            let (left_image, right_image) = diff::open_and_decode_images(
+                &left_path,
+                &right_path,
+                &params.color_space,
+                params.ignore_orientation,
+            )?;
+            diff::check_dimensions(&left_image, &right_image, params)
+                .with_context(|| format!("in frame {}", number.to_string().magenta()).red())?;
+
+            let (result, metrics) =
+                diff::get_results_and_metrics(&left_image, &right_image, params);
+            let diffs = result.as_ref().map_or(0, |result| result.diffs);
+            let anti_aliased = result.as_ref().map_or(0, |result| result.anti_aliased);
+
+            // identical frames get an empty diff to keep the diff sequence free of gaps
+            if let Some(output) = output {
+                let image = result.map_or_else(
+                    || RgbaImage::new(left_image.width(), left_image.height()),
+                    |result| result.image,
+                );
+                let image = diff::apply_layout(&left_image, &right_image, image, params);
+                write_diff(&output.format(number), &image)?;
+            }
+
+            Ok(FrameResult::Compared {
+                diffs,
+                anti_aliased,
+                metrics,
+            })
+        }
+        (true, false) | (false, true) => {
+            let (image, which) = match left.exists(number) {
+                true => (
+                    diff::open_and_decode_image(
+                        &left_path,
+                        "left",
+                        &params.color_space,
+                        params.ignore_orientation,
+                    )?,
+                    "left",
+                ),
+                false => (
+                    diff::open_and_decode_image(
+                        &right_path,
+                        "right",
+                        &params.color_space,
+                        params.ignore_orientation,
+                    )?,
+                    "right",
+                ),
+            };
+            let (width, height) = image.dimensions();
+
+            if let Some(output) = output {
+                let missing = RgbaImage::new(width, height);
+                let diff_image = RgbaImage::from_pixel(width, height, params.diff_color);
+                let diff_image = match which {
+                    "left" => diff::apply_layout(&image, &missing, diff_image, params),
+                    _ => diff::apply_layout(&missing, &image, diff_image, params),
+                };
+                write_diff(&output.format(number), &diff_image)?;
+            }
+
+            let pixels = u64::from(width) * u64::from(height);
+            Ok(FrameResult::Missing(
+                i32::try_from(pixels).unwrap_or(i32::MAX),
+            ))
+        }
+        (false, false) => Ok(FrameResult::Missing(0)),
+    }
+}
+
+fn curve(diffs: i32, max: i32) -> String {
+    match max {
+        0 => String::new(),
+        // rounded up so that every different frame gets a bar
+        _ => "#".repeat(
+            ((i64::from(diffs) * CURVE_WIDTH + i64::from(max) - 1) / i64::from(max)) as usize,
+        ),
+    }
+}
+
+// compares the frames of two image sequences with the same number, e.g. `left_%04d.png` and
+// `right_%04d.png`; the diff sequence is only written when the output is a pattern as well
+pub fn run(params: &diff::RunParams) -> Result<Option<i32>> {
+    diff::reject_single_image_outputs(params, "image sequences")?;
+
+    let left = parse_pattern(params.left, "LEFT")?;
+    let right = parse_pattern(params.right, "RIGHT")?;
+    let output = Pattern::parse(params.output);
+
+    // rendered sequences start either at 0 or at 1
+    let first = (0..=1)
+        .find(|number| left.exists(*number) || right.exists(*number))
+        .ok_or_else(|| {
+            anyhow!(format!(
+                "no frames found for \"{}\" and \"{}\"",
+                params.left.magenta(),
+                params.right.magenta()
+            )
+            .red())
+        })?;
+
+    let left_count = (first..).take_while(|number| left.exists(*number)).count() as u32;
+    let right_count = (first..).take_while(|number| right.exists(*number)).count() as u32;
+
+    if left_count != right_count {
+        println!(
+            "frame counts are different, left: {}, right: {}",
+            left_count.to_string().magenta(),
+            right_count.to_string().magenta()
+        );
+    }
+
+    let numbers: Vec<u32> = (first..first + left_count.max(right_count)).collect();
+    let results = numbers
+        .par_iter()
+        .map(|number| compare_frame(params, &left, &right, output.as_ref(), *number))
+        .collect::<Result<Vec<FrameResult>>>()?;
+
+    let max = results.iter().map(FrameResult::diffs).max().unwrap_or(0);
+
+    let mut diffs: i32 = 0;
+    let mut anti_aliased: i32 = 0;
+    let mut first_different = None;
+
+    for (number, result) in numbers.iter().zip(results.iter()) {
+        match result {
+            FrameResult::Compared { diffs: 0, .. } => {
+                println!("frame {}: no differences", number)
+            }
+            FrameResult::Compared {
+                diffs: frame_diffs, ..
+            } => println!(
+                "frame {}: {} different pixels {}",
+                number,
+                frame_diffs,
+                curve(*frame_diffs, max).red()
+            ),
+            FrameResult::Missing(frame_diffs) => println!(
+                "frame {}: missing on one side {}",
+                number,
+                curve(*frame_diffs, max).red()
+            ),
+        }
+
+        if let FrameResult::Compared {
+            anti_aliased: frame_anti_aliased,
+            metrics,
+            ..
+        } = result
+        {
+            if *frame_anti_aliased > 0 {
+                println!(
+                    "frame {}: {} anti-aliased pixels",
+                    number, frame_anti_aliased
+                );
+                anti_aliased += frame_anti_aliased;
+            }
+
+            if params.print_metrics {
+                println!("frame {}: {}", number, metrics);
+            }
+        }
+
+        if result.diffs() > 0 {
+            first_different = first_different.or(Some(number));
+            diffs = diffs.saturating_add(result.diffs());
+        }
+    }
+
+    match first_different {
+        Some(number) => println!("first different frame: {}", number.to_string().red()),
+        None => println!("{}", "no differences".green()),
+    }
+
+    diff::check_anti_aliased_budget(params, anti_aliased)?;
+
+    Ok(Some(diffs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_pattern() {
+        let pattern = Pattern::parse("frames/left_%04d.png").unwrap();
+        assert_eq!("frames/left_0012.png", pattern.format(12));
+        assert_eq!("frames/left_12345.png", pattern.format(12345));
+
+        assert_eq!("7.png", Pattern::parse("%d.png").unwrap().format(7));
+        assert_eq!("  7.png", Pattern::parse("%3d.png").unwrap().format(7));
+        assert_eq!(
+            "100%_7.png",
+            Pattern::parse("100%%_%d.png").unwrap().format(7)
+        );
+    }
+
+    #[test]
+    fn test_parse_pattern_invalid() {
+        assert_eq!(None, Pattern::parse("left.png"));
+        assert_eq!(None, Pattern::parse("left_%s.png"));
+        assert_eq!(None, Pattern::parse("left_%d_%d.png"));
+        assert_eq!(None, Pattern::parse("left_%04"));
+    }
+
+    #[test]
+    fn test_curve() {
+        assert_eq!("", curve(0, 0));
+        assert_eq!("#", curve(1, 1000));
+        assert_eq!("#".repeat(20), curve(500, 1000));
+        assert_eq!("#".repeat(40), curve(1000, 1000));
+    }
+}
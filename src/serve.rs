@@ -0,0 +1,283 @@
+use super::diff;
+use anyhow::{anyhow, Context, Result};
+use colored::*;
+use image::ImageFormat;
+use std::collections::hash_map::RandomState;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "gif", "webp"];
+const PAGE: &str = include_str!("serve.html");
+const TOKEN_HEADER: &str = "X-Dify-Token";
+
+// relative paths of the images found in the directory and its subdirectories
+fn list_images(root: &Path, dir: &Path, images: &mut Vec<String>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            list_images(root, &path, images)?;
+        } else if path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        {
+            if let Some(name) = path.strip_prefix(root)?.to_str() {
+                images.push(name.replace('\\', "/"));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// images present in both directories, which are the only ones the server gives access to
+fn list_comparisons(baseline: &Path, actual: &Path) -> Result<Vec<String>> {
+    let mut images = Vec::new();
+    list_images(baseline, baseline, &mut images)?;
+    images.retain(|name| actual.join(name).is_file());
+    images.sort();
+    Ok(images)
+}
+
+fn header(name: &str, value: &str) -> Result<Header> {
+    Header::from_bytes(name, value)
+        .map_err(|_| anyhow!("invalid header {}: {}", name, value.magenta()))
+}
+
+// a random token of the run, which the page sends along when approving so that other sites
+// cannot, as they never get to read the page
+fn random_token() -> String {
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+// guards against requests of other sites, either cross-origin or through a DNS name rebound to
+// the loopback interface
+fn is_same_origin(request: &Request, port: u16) -> bool {
+    let hosts = [format!("127.0.0.1:{port}"), format!("localhost:{port}")];
+
+    let host_matches =
+        header_value(request, "Host").is_some_and(|host| hosts.iter().any(|h| h == host));
+    let origin_matches = header_value(request, "Origin")
+        .is_none_or(|origin| hosts.iter().any(|host| origin == format!("http://{host}")));
+
+    host_matches && origin_matches
+}
+
+fn has_token(request: &Request, token: &str) -> bool {
+    header_value(request, TOKEN_HEADER) == Some(token)
+}
+
+fn header_value<'a>(request: &'a Request, name: &'static str) -> Option<&'a str> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str())
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_page(comparisons: &[String], threshold: f32, token: &str) -> String {
+    let items: String = comparisons
+        .iter()
+        .map(|name| format!("<li data-name=\"{}\"></li>", html_escape(name)))
+        .collect();
+
+    PAGE.replace("{{threshold}}", &threshold.to_string())
+        .replace("{{token}}", token)
+        .replace("{{comparisons}}", &items)
+}
+
+fn render_diff(params: &diff::RunParams, baseline: &Path, actual: &Path) -> Result<(i32, Vec<u8>)> {
+    let (left, right) = (baseline.to_string_lossy(), actual.to_string_lossy());
+    let (left_image, right_image) = diff::open_and_decode_images(
+        &left,
+        &right,
+        &params.color_space,
+        params.ignore_orientation,
+    )?;
+
+    // unchanged pixels are always drawn so that there is an image to show for identical ones
+    let params = diff::RunParams {
+        blend_factor_of_unchanged_pixels: params.blend_factor_of_unchanged_pixels.or(Some(0.1)),
+        ..params.clone()
+    };
+
+    let output = diff::get_results(&left_image, &right_image, &params)
+        .ok_or_else(|| anyhow!("nothing to render"))?;
+    let mut png = Vec::new();
+    output
+        .image
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+
+    Ok((output.diffs, png))
+}
+
+fn query_threshold(url: &str) -> Option<f32> {
+    url.split_once('?')?
+        .1
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == "threshold")
+        .and_then(|(_, value)| value.parse::<f32>().ok())
+}
+
+fn decode_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(hex)) => match u8::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+                Ok(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                Err(_) => decoded.push(b'%'),
+            },
+            (byte, _) => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// what the server reviews, along with what it takes for a request to be accepted
+struct Review<'a> {
+    params: &'a diff::RunParams<'a>,
+    baseline: PathBuf,
+    actual: PathBuf,
+    port: u16,
+    token: String,
+}
+
+fn handle(request: &Request, review: &Review) -> Result<Response<Cursor<Vec<u8>>>> {
+    let (params, baseline, actual) = (review.params, &review.baseline, &review.actual);
+
+    if !is_same_origin(request, review.port) {
+        return Ok(Response::from_string("forbidden").with_status_code(403));
+    }
+
+    let url = request.url();
+    let path = decode_path(url.split('?').next().unwrap_or(url));
+    let path = path.trim_start_matches('/');
+    let (kind, name) = path.split_once('/').unwrap_or((path, ""));
+    let comparisons = list_comparisons(baseline, actual)?;
+    let known = comparisons.iter().any(|comparison| comparison == name);
+
+    let response = match (request.method(), kind, known) {
+        (Method::Get, "", _) => {
+            Response::from_string(render_page(&comparisons, params.threshold, &review.token))
+                .with_header(header("Content-Type", "text/html; charset=utf-8")?)
+        }
+        (Method::Get, "baseline", true) => Response::from_data(fs::read(baseline.join(name))?),
+        (Method::Get, "actual", true) => Response::from_data(fs::read(actual.join(name))?),
+        (Method::Get, "diff", true) => {
+            let params = diff::RunParams {
+                threshold: query_threshold(url).unwrap_or(params.threshold),
+                ..params.clone()
+            };
+            let (diffs, png) = render_diff(&params, &baseline.join(name), &actual.join(name))?;
+
+            Response::from_data(png)
+                .with_header(header("Content-Type", "image/png")?)
+                .with_header(header("X-Diff-Pixels", &diffs.to_string())?)
+        }
+        (Method::Post, "approve", true) if !has_token(request, &review.token) => {
+            Response::from_string("forbidden").with_status_code(403)
+        }
+        (Method::Post, "approve", true) => {
+            fs::copy(actual.join(name), baseline.join(name))?;
+            println!("approved \"{}\"", name.magenta());
+            Response::from_string("")
+        }
+        _ => Response::from_string("not found").with_status_code(404),
+    };
+
+    Ok(response)
+}
+
+// serves an interactive review of the images found in both the baseline and the actual directory
+pub fn run(params: &diff::RunParams, port: u16) -> Result<()> {
+    let baseline = PathBuf::from(params.left);
+    let actual = PathBuf::from(params.right);
+
+    for (dir, which) in [(&baseline, "baseline"), (&actual, "actual")].iter() {
+        if !dir.is_dir() {
+            return Err(anyhow!(format!(
+                "the {} directory \"{}\" does not exist",
+                which,
+                dir.display().to_string().magenta()
+            )
+            .red()));
+        }
+    }
+
+    // bound to the loopback interface only, approving writes into the baseline directory
+    let address = format!("127.0.0.1:{port}");
+    let server = Server::http(&address)
+        .map_err(|error| anyhow!(error))
+        .with_context(|| format!("failed to listen on {}", address.magenta()).red())?;
+
+    println!("reviewing on {}", format!("http://{address}/").magenta());
+
+    serve(
+        &server,
+        &Review {
+            params,
+            baseline,
+            actual,
+            port,
+            token: random_token(),
+        },
+    );
+
+    Ok(())
+}
+
+fn serve(server: &Server, review: &Review) {
+    for request in server.incoming_requests() {
+        let response = handle(&request, review).unwrap_or_else(|error| {
+            Response::from_string(format!("{error:#}")).with_status_code(500)
+        });
+
+        if let Err(error) = request.respond(response) {
+            eprintln!("{} {}", "Error:".red(), error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_threshold() {
+        assert_eq!(Some(0.25), query_threshold("/diff/a.png?threshold=0.25"));
+        assert_eq!(Some(0.5), query_threshold("/diff/a.png?x=1&threshold=0.5"));
+        assert_eq!(None, query_threshold("/diff/a.png?threshold=abc"));
+        assert_eq!(None, query_threshold("/diff/a.png"));
+    }
+
+    #[test]
+    fn test_decode_path() {
+        assert_eq!("/diff/a b.png", decode_path("/diff/a%20b.png"));
+        assert_eq!("/diff/dir/ü.png", decode_path("/diff/dir/%C3%BC.png"));
+        assert_eq!("/diff/100%.png", decode_path("/diff/100%.png"));
+        assert_eq!("/diff/%zz.png", decode_path("/diff/%zz.png"));
+    }
+}
@@ -0,0 +1,223 @@
+use super::{cli, diff::DiffResult, yiq::Measure};
+use image::RgbaImage;
+use std::collections::VecDeque;
+
+// Sobel gradient magnitude of the luminance above which a pixel of the left image counts as an
+// edge, e.g. a step of 32 between neighbouring columns
+const EDGE_THRESHOLD: f32 = 128.0;
+
+fn luminance(image: &RgbaImage, background: &cli::Background, measure: &Measure) -> Vec<f32> {
+    image
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| measure.yiq(pixel, background.at(x, y)).luminance())
+        .collect()
+}
+
+// pixels where the luminance changes sharply, such as the outlines of glyphs
+fn edges(image: &RgbaImage, background: &cli::Background, measure: &Measure) -> Vec<bool> {
+    let (width, height) = image.dimensions();
+    let luminance = luminance(image, background, measure);
+    // neighbours beyond the borders repeat the pixels at the borders
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, i64::from(width) - 1) as u32;
+        let y = y.clamp(0, i64::from(height) - 1) as u32;
+        luminance[(y * width + x) as usize]
+    };
+
+    let mut edges = vec![false; luminance.len()];
+    for y in 0..i64::from(height) {
+        for x in 0..i64::from(width) {
+            let gx = at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x - 1, y)
+                - at(x - 1, y + 1);
+            let gy = at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1)
+                - at(x - 1, y - 1)
+                - 2.0 * at(x, y - 1)
+                - at(x + 1, y - 1);
+
+            edges[(y * i64::from(width) + x) as usize] = gx.hypot(gy) > EDGE_THRESHOLD;
+        }
+    }
+
+    edges
+}
+
+// grows the mask by the radius in every direction, one axis at a time
+fn dilate(mask: &[bool], width: u32, height: u32, radius: u32) -> Vec<bool> {
+    let (width, height, radius) = (width as usize, height as usize, radius as usize);
+    let mut horizontal = vec![false; mask.len()];
+
+    for y in 0..height {
+        let row = &mask[y * width..(y + 1) * width];
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius).min(width - 1));
+            horizontal[y * width + x] = row[x0..=x1].contains(&true);
+        }
+    }
+
+    let mut dilated = vec![false; mask.len()];
+    for x in 0..width {
+        for y in 0..height {
+            let (y0, y1) = (y.saturating_sub(radius), (y + radius).min(height - 1));
+            dilated[y * width + x] = (y0..=y1).any(|y| horizontal[y * width + x]);
+        }
+    }
+
+    dilated
+}
+
+// differences confined to the band of the given width around edges in the left image are the
+// typical fringes of glyphs rendered with different hinting, so every cluster of different pixels
+// lying entirely within the band is reclassified as anti-aliased; results are expected in the
+// order of `enumerate_pixels`
+pub fn tolerate(
+    left: &RgbaImage,
+    results: Vec<DiffResult>,
+    band_width: u32,
+    background: &cli::Background,
+    measure: &Measure,
+) -> Vec<DiffResult> {
+    let (width, height) = left.dimensions();
+    let band = dilate(&edges(left, background, measure), width, height, band_width);
+    let different: Vec<bool> = results
+        .iter()
+        .map(|result| matches!(result, DiffResult::Different(..)))
+        .collect();
+
+    let mut tolerated = vec![false; different.len()];
+    let mut visited = vec![false; different.len()];
+    let mut queue = VecDeque::new();
+    let mut cluster = Vec::new();
+
+    for start in 0..different.len() {
+        if !different[start] || visited[start] {
+            continue;
+        }
+
+        // flood fill of the 8-connected cluster of different pixels
+        visited[start] = true;
+        queue.push_back(start);
+        cluster.clear();
+
+        while let Some(index) = queue.pop_front() {
+            cluster.push(index);
+            let (x, y) = (
+                (index % width as usize) as i64,
+                (index / width as usize) as i64,
+            );
+
+            for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= i64::from(width) || ny >= i64::from(height) {
+                    continue;
+                }
+
+                let neighbour = (ny * i64::from(width) + nx) as usize;
+                if different[neighbour] && !visited[neighbour] {
+                    visited[neighbour] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        if cluster.iter().all(|index| band[*index]) {
+            for index in &cluster {
+                tolerated[*index] = true;
+            }
+        }
+    }
+
+    results
+        .into_iter()
+        .zip(tolerated)
+        .map(|(result, tolerated)| match result {
+            DiffResult::Different(x, y, _) if tolerated => DiffResult::AntiAliased(x, y),
+            result => result,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    #[test]
+    fn test_dilate() {
+        let mut mask = vec![false; 25];
+        mask[12] = true;
+
+        let actual = dilate(&mask, 5, 5, 1);
+        let expected: Vec<bool> = (0..25)
+            .map(|index| (1..=3).contains(&(index % 5)) && (1..=3).contains(&(index / 5)))
+            .collect();
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_edges() {
+        // a vertical black stroke on white
+        let image = RgbaImage::from_fn(7, 3, |x, _| if x == 3 { BLACK } else { WHITE });
+        let actual = edges(&image, &cli::Background::White, &Measure::default());
+
+        for (index, edge) in actual.iter().enumerate() {
+            // the gradient vanishes in the middle of a stroke one pixel wide
+            assert_eq!(matches!(index % 7, 2 | 4), *edge, "at {}", index);
+        }
+    }
+
+    // a black stroke at column 5 of a 12x12 white image, with the given differences
+    fn results(different: &[(u32, u32)]) -> (RgbaImage, Vec<DiffResult>) {
+        let image = RgbaImage::from_fn(12, 12, |x, _| if x == 5 { BLACK } else { WHITE });
+        let results = image
+            .enumerate_pixels()
+            .map(|(x, y, _)| match different.contains(&(x, y)) {
+                true => DiffResult::Different(x, y, 1.0),
+                false => DiffResult::Identical(x, y),
+            })
+            .collect();
+
+        (image, results)
+    }
+
+    #[test]
+    fn test_tolerate_fringe() {
+        let fringe: Vec<(u32, u32)> = (2..8).map(|y| (6, y)).collect();
+        let (image, results) = results(&fringe);
+        let actual = tolerate(
+            &image,
+            results,
+            1,
+            &cli::Background::White,
+            &Measure::default(),
+        );
+
+        assert!(actual
+            .iter()
+            .all(|result| !matches!(result, DiffResult::Different(..))));
+        assert_eq!(DiffResult::AntiAliased(6, 2), actual[2 * 12 + 6]);
+    }
+
+    #[test]
+    fn test_tolerate_beyond_band() {
+        // connected to the fringe, but reaching further than the band around the stroke
+        let mut different: Vec<(u32, u32)> = (2..8).map(|y| (6, y)).collect();
+        different.extend((7..11).map(|x| (x, 7)));
+        let (image, results) = results(&different);
+        let actual = tolerate(
+            &image,
+            results,
+            1,
+            &cli::Background::White,
+            &Measure::default(),
+        );
+
+        assert_eq!(DiffResult::Different(6, 2, 1.0), actual[2 * 12 + 6]);
+        assert_eq!(DiffResult::Different(10, 7, 1.0), actual[7 * 12 + 10]);
+    }
+}
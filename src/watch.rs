@@ -0,0 +1,146 @@
+use super::diff;
+use anyhow::{Context, Result};
+use colored::*;
+use notify::{RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+// renderers usually write their output in several chunks, so wait until the inputs have been
+// quiet for a while before comparing them again
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+fn absolute(path: &str) -> Result<PathBuf> {
+    let path = Path::new(path);
+    let path = if path.is_absolute() {
+        path.to_owned()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    Ok(path)
+}
+
+fn print_summary(params: &diff::RunParams, result: &Result<Option<i32>>) {
+    match result {
+        Ok(Some(0)) | Ok(None) => println!("{}", "no differences".green()),
+        Ok(Some(diffs)) => println!(
+            "{} different pixels, see \"{}\"",
+            diffs.to_string().red(),
+            params.output.magenta()
+        ),
+        Err(error) => eprintln!("{} {:?}", "Error:".red(), error),
+    }
+}
+
+// whether the event changed one of the inputs; reading them, e.g. to compare them, doesn't
+fn is_input(event: &notify::Result<notify::Event>, inputs: &HashSet<PathBuf>) -> bool {
+    match event {
+        Ok(event) => {
+            !event.kind.is_access() && event.paths.iter().any(|path| inputs.contains(path))
+        }
+        Err(_) => false,
+    }
+}
+
+// drops further events until none has arrived for the debounce period
+fn debounce<T>(receiver: &mpsc::Receiver<T>) {
+    while receiver.recv_timeout(DEBOUNCE).is_ok() {}
+}
+
+// compares the images, then again every time one of them changes, until interrupted
+pub fn run(params: &diff::RunParams) -> Result<()> {
+    let inputs: HashSet<PathBuf> = vec![absolute(params.left)?, absolute(params.right)?]
+        .into_iter()
+        .collect();
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+
+    // editors and renderers often replace files instead of writing them in place, which drops
+    // watches on the files themselves, so the directories containing them are watched instead
+    let dirs: HashSet<&Path> = inputs.iter().filter_map(|path| path.parent()).collect();
+    for dir in dirs {
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .with_context(|| {
+                format!(
+                    "failed to watch \"{}\"",
+                    dir.display().to_string().magenta()
+                )
+                .red()
+            })?;
+    }
+
+    print_summary(params, &diff::run(params));
+
+    loop {
+        let event = receiver.recv()?;
+        if !is_input(&event, &inputs) {
+            continue;
+        }
+
+        debounce(&receiver);
+
+        print_summary(params, &diff::run(params));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+    use notify::{Event, EventKind};
+    use std::time::Instant;
+
+    #[test]
+    fn test_is_input() {
+        let inputs: HashSet<PathBuf> = vec![PathBuf::from("/images/left.png")]
+            .into_iter()
+            .collect();
+        let event = |kind, path: &str| Ok(Event::new(kind).add_path(PathBuf::from(path)));
+
+        assert!(is_input(
+            &event(EventKind::Modify(ModifyKind::Any), "/images/left.png"),
+            &inputs
+        ));
+        assert!(is_input(
+            &event(EventKind::Create(CreateKind::File), "/images/left.png"),
+            &inputs
+        ));
+        assert!(!is_input(
+            &event(EventKind::Access(AccessKind::Any), "/images/left.png"),
+            &inputs
+        ));
+        assert!(!is_input(
+            &event(EventKind::Modify(ModifyKind::Any), "/images/diff.png"),
+            &inputs
+        ));
+        assert!(!is_input(
+            &Err(notify::Error::generic("watch failed")),
+            &inputs
+        ));
+    }
+
+    #[test]
+    fn test_debounce() {
+        let (sender, receiver) = mpsc::channel();
+        let writer = std::thread::spawn(move || {
+            for chunk in 0..3 {
+                sender.send(chunk).unwrap();
+                std::thread::sleep(DEBOUNCE / 4);
+            }
+            // keeps the channel open, so that only the quiet period ends the debounce
+            std::thread::sleep(DEBOUNCE * 2);
+            sender.send(3).unwrap();
+        });
+
+        let start = Instant::now();
+        debounce(&receiver);
+
+        // every chunk written in quick succession is dropped, but not the later change
+        assert!(start.elapsed() >= DEBOUNCE + DEBOUNCE / 4);
+        writer.join().unwrap();
+        assert_eq!(Ok(3), receiver.try_recv());
+    }
+}
@@ -1,5 +1,44 @@
 use image::Pixel;
 
+// the maximum possible YIQ delta, as used by pixelmatch to scale the threshold
+pub const MAX_SQUARED_DISTANCE: f32 = 35215.0;
+
+// decodes a gamma-encoded sRGB value into linear light, both on a scale of 0 to 255
+pub fn linearize(value: f32) -> f32 {
+    let value = value / 255.0;
+    let linear = match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    };
+
+    linear * 255.0
+}
+
+// how pixels are converted and compared
+#[derive(Clone, Copy, Default)]
+pub struct Measure {
+    // blends and converts linear light instead of the gamma-encoded values
+    pub linear: bool,
+    // compares the luminance only
+    pub luminance_only: bool,
+}
+
+impl Measure {
+    pub fn yiq(&self, rgba: &image::Rgba<u8>, background: f32) -> Yiq {
+        match self.linear {
+            true => Yiq::from_linear_rgba(rgba, background),
+            false => Yiq::from_rgba(rgba, background),
+        }
+    }
+
+    pub fn delta(&self, left: &Yiq, right: &Yiq) -> f32 {
+        match self.luminance_only {
+            true => left.squared_luminance_distance(right),
+            false => left.squared_distance(right),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Yiq {
     y: f32, // luminance
@@ -8,47 +47,76 @@ pub struct Yiq {
 }
 
 impl Yiq {
-    #[allow(clippy::many_single_char_names, clippy::excessive_precision)]
+    #[allow(clippy::excessive_precision)]
+    fn y(r: f32, g: f32, b: f32) -> f32 {
+        0.298_895_31 * r + 0.586_622_47 * g + 0.114_482_23 * b
+    }
+
+    #[allow(clippy::excessive_precision)]
+    fn i(r: f32, g: f32, b: f32) -> f32 {
+        0.595_977_99 * r - 0.274_171_6 * g - 0.321_801_89 * b
+    }
+
+    #[allow(clippy::excessive_precision)]
+    fn q(r: f32, g: f32, b: f32) -> f32 {
+        0.211_470_19 * r - 0.522_617_11 * g + 0.311_146_94 * b
+    }
+
     pub fn rgb2y(rgb: &image::Rgb<u8>) -> f32 {
         let rgb = rgb.channels();
-        let r = f32::from(rgb[0]);
-        let g = f32::from(rgb[1]);
-        let b = f32::from(rgb[2]);
+        Self::y(f32::from(rgb[0]), f32::from(rgb[1]), f32::from(rgb[2]))
+    }
 
-        0.298_895_31 * r + 0.586_622_47 * g + 0.114_482_23 * b
+    // blends the pixel onto the given background (0 for black, 255 for white) before converting,
+    // so that fully transparent pixels are equal regardless of their color channels
+    pub fn from_rgba(rgba: &image::Rgba<u8>, background: f32) -> Self {
+        let rgba = rgba.channels();
+        let alpha = f32::from(rgba[3]) / 255.0;
+        let r = super::blend(f32::from(rgba[0]), alpha, background);
+        let g = super::blend(f32::from(rgba[1]), alpha, background);
+        let b = super::blend(f32::from(rgba[2]), alpha, background);
+
+        Self::from_rgb(r, g, b)
     }
 
-    #[allow(clippy::many_single_char_names, clippy::excessive_precision)]
-    fn rgb2i(rgb: &image::Rgb<u8>) -> f32 {
-        let rgb = rgb.channels();
-        let r = f32::from(rgb[0]);
-        let g = f32::from(rgb[1]);
-        let b = f32::from(rgb[2]);
+    // like `from_rgba`, but blends and converts the values in linear light, on the same scale
+    pub fn from_linear_rgba(rgba: &image::Rgba<u8>, background: f32) -> Self {
+        let rgba = rgba.channels();
+        let alpha = f32::from(rgba[3]) / 255.0;
+        let background = linearize(background);
+        let r = super::blend(linearize(f32::from(rgba[0])), alpha, background);
+        let g = super::blend(linearize(f32::from(rgba[1])), alpha, background);
+        let b = super::blend(linearize(f32::from(rgba[2])), alpha, background);
 
-        0.595_977_99 * r - 0.274_171_6 * g - 0.321_801_89 * b
+        Self::from_rgb(r, g, b)
     }
 
-    #[allow(clippy::many_single_char_names, clippy::excessive_precision)]
-    fn rgb2q(rgb: &image::Rgb<u8>) -> f32 {
-        let rgb = rgb.channels();
-        let r = f32::from(rgb[0]);
-        let g = f32::from(rgb[1]);
-        let b = f32::from(rgb[2]);
+    fn from_rgb(r: f32, g: f32, b: f32) -> Self {
+        Self {
+            y: Self::y(r, g, b),
+            i: Self::i(r, g, b),
+            q: Self::q(r, g, b),
+        }
+    }
 
-        0.211_470_19 * r - 0.522_617_11 * g + 0.311_146_94 * b
+    pub fn luminance(&self) -> f32 {
+        self.y
     }
 
-    pub fn from_rgba(rgba: &image::Rgba<u8>) -> Self {
-        let rgb = rgba.to_rgb();
-        let y = Self::rgb2y(&rgb);
-        let i = Self::rgb2i(&rgb);
-        let q = Self::rgb2q(&rgb);
+    // the luminance term of `squared_distance` only, so that changes between grays are as far apart
+    // in both
+    pub fn squared_luminance_distance(&self, other: &Self) -> f32 {
+        let delta = 0.5053 * (self.y - other.y).powi(2);
 
-        Self { y, i, q }
+        if self.y > other.y {
+            -delta
+        } else {
+            delta
+        }
     }
 
-    pub fn delta_y(left: &image::Rgb<u8>, right: &image::Rgb<u8>) -> f32 {
-        Self::rgb2y(left) - Self::rgb2y(right)
+    pub fn delta_y(&self, other: &Self) -> f32 {
+        self.y - other.y
     }
 
     // in the performance critical applications, square root can be omiitted
@@ -68,7 +136,7 @@ impl Yiq {
 
 #[cfg(test)]
 mod tests {
-    use super::Yiq;
+    use super::{linearize, Yiq};
 
     #[test]
     fn test_from_rgb() {
@@ -77,7 +145,7 @@ mod tests {
             i: 0.0,
             q: 0.0,
         };
-        let actual = Yiq::from_rgba(&image::Rgba([0, 0, 0, 0]));
+        let actual = Yiq::from_rgba(&image::Rgba([0, 0, 0, 0]), 0.0);
         assert_eq!(expected, actual);
     }
 
@@ -95,4 +163,65 @@ mod tests {
         };
         assert_eq!(a.squared_distance(&b), 0.0);
     }
+
+    #[test]
+    fn test_from_rgba_transparent() {
+        let red = Yiq::from_rgba(&image::Rgba([255, 0, 0, 0]), 255.0);
+        let blue = Yiq::from_rgba(&image::Rgba([0, 0, 255, 0]), 255.0);
+        assert_eq!(red, blue);
+        assert_eq!(Yiq::from_rgba(&image::Rgba([255, 255, 255, 255]), 0.0), red);
+    }
+
+    #[test]
+    fn test_squared_luminance_distance() {
+        let black = Yiq::from_rgba(&image::Rgba([0, 0, 0, 255]), 255.0);
+        let white = Yiq::from_rgba(&image::Rgba([255, 255, 255, 255]), 255.0);
+        let red = Yiq::from_rgba(&image::Rgba([255, 0, 0, 255]), 255.0);
+        let gray = Yiq::from_rgba(&image::Rgba([76, 76, 76, 255]), 255.0);
+
+        assert_eq!(
+            black.squared_distance(&white),
+            black.squared_luminance_distance(&white)
+        );
+        assert_eq!(
+            white.squared_distance(&gray),
+            white.squared_luminance_distance(&gray)
+        );
+        // a red and a gray of the same luminance only differ in color
+        assert!(red.squared_luminance_distance(&gray).abs() < 1.0);
+        assert!(red.squared_distance(&gray).abs() > 1000.0);
+    }
+
+    #[test]
+    fn test_linearize() {
+        assert_eq!(0.0, linearize(0.0));
+        assert_eq!(255.0, linearize(255.0));
+        assert!((linearize(128.0) - 55.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_from_linear_rgba() {
+        let black = image::Rgba([0, 0, 0, 255]);
+        let dark = image::Rgba([16, 16, 16, 255]);
+        let light = image::Rgba([239, 239, 239, 255]);
+        let white = image::Rgba([255, 255, 255, 255]);
+        let distance = |convert: fn(&image::Rgba<u8>, f32) -> Yiq, a, b| {
+            convert(a, 255.0).squared_distance(&convert(b, 255.0)).abs()
+        };
+
+        // the same step of the gamma-encoded values is a smaller change of light in the shadows
+        // and a larger one in the highlights
+        assert!(
+            distance(Yiq::from_linear_rgba, &black, &dark)
+                < distance(Yiq::from_rgba, &black, &dark)
+        );
+        assert!(
+            distance(Yiq::from_linear_rgba, &light, &white)
+                > distance(Yiq::from_rgba, &light, &white)
+        );
+
+        // half transparent black on white is half as much light
+        let half = Yiq::from_linear_rgba(&image::Rgba([0, 0, 0, 128]), 255.0);
+        assert!((half.luminance() - 127.5).abs() < 1.0);
+    }
 }
@@ -1,9 +1,10 @@
 use assert_cmd::Command;
 use assert_fs::assert::PathAssert;
-use assert_fs::fixture::NamedTempFile;
+use assert_fs::fixture::{FileWriteStr, NamedTempFile, PathChild, TempDir};
 use predicates::prelude::*;
 use std::env::consts;
 use std::fs;
+use std::io;
 use std::path;
 
 #[test]
@@ -42,10 +43,10 @@ fn test_left_does_not_exist() {
 Caused by:
     {} (os error 2)
 "#,
-        left.display().to_string(),
+        left.display(),
         match consts::OS {
             "windows" => "The system cannot find the file specified.",
-            "linux" | "macos" | _ => "No such file or directory",
+            _ => "No such file or directory",
         }
     ));
 }
@@ -64,10 +65,10 @@ fn test_right_does_not_exist() {
 Caused by:
     {} (os error 2)
 "#,
-        right.display().to_string(),
+        right.display(),
         match consts::OS {
             "windows" => "The system cannot find the file specified.",
-            "linux" | "macos" | _ => "No such file or directory",
+            _ => "No such file or directory",
         }
     ));
 }
@@ -89,6 +90,26 @@ fn test_identical_image() {
     output.close().unwrap();
 }
 
+#[test]
+fn test_mask_output_identical_image() {
+    let dir = TempDir::new().unwrap();
+    let mask = dir.child("mask.png");
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    let assert = cmd
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg("--output")
+        .arg(dir.child("diff.png").path())
+        .arg("--mask-output")
+        .arg(mask.path());
+
+    assert.assert().success();
+    let mask = image::open(mask.path()).unwrap().into_luma8();
+    assert!(mask.pixels().all(|pixel| pixel.0 == [0]));
+
+    dir.close().unwrap();
+}
+
 #[test]
 fn test_different_image() {
     let output = NamedTempFile::new("test_different_image-diff.png").unwrap();
@@ -101,7 +122,7 @@ fn test_different_image() {
 
     assert.assert().code(match consts::OS {
         "windows" => 7787,
-        "linux" | "macos" | _ => 106,
+        _ => 106,
     });
 
     output.close().unwrap();
@@ -192,3 +213,409 @@ fn test_block_out_area() {
 
     output.close().unwrap();
 }
+
+#[test]
+fn test_crop_to_diff() {
+    let output = NamedTempFile::new("test_crop_to_diff-diff.png").unwrap();
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    let assert = cmd
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap())
+        .arg("--output")
+        .arg(output.path().display().to_string())
+        .arg("--crop-to-diff")
+        .arg("--crop-padding")
+        .arg("10");
+
+    assert
+        .assert()
+        .failure()
+        .stdout("cropped to 313x194+577+3\n");
+    assert_eq!((313, 194), image::image_dimensions(output.path()).unwrap());
+
+    output.close().unwrap();
+}
+
+#[test]
+fn test_config_file() {
+    let dir = TempDir::new().unwrap();
+    dir.child("dify.toml")
+        .write_str("threshold = 1.0\noutput = \"from-config.png\"\n")
+        .unwrap();
+    let left = fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap();
+    let right = fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path()).arg(&left).arg(&right);
+    cmd.assert().success();
+
+    // the output is relative to the config file rather than the working directory
+    let sub = dir.child("sub");
+    fs::create_dir(sub.path()).unwrap();
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(sub.path())
+        .arg(&left)
+        .arg(&right)
+        .arg("--threshold")
+        .arg("0.1");
+    cmd.assert().failure();
+    dir.child("from-config.png")
+        .assert(predicate::path::exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_help_with_broken_config_file() {
+    let dir = TempDir::new().unwrap();
+    dir.child("dify.toml").write_str("threshold = \n").unwrap();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path()).arg("--help");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("Usage:"));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_block_out_mask() {
+    let dir = TempDir::new().unwrap();
+    let mask = dir.child("mask.png");
+    let mut image = image::RgbaImage::new(1000, 667);
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        if (550..900).contains(&x) && y < 200 {
+            *pixel = image::Rgba([255, 255, 255, 255]);
+        }
+    }
+    image.save(mask.path()).unwrap();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap())
+        .arg("--block-out-mask")
+        .arg(mask.path());
+    cmd.assert().success();
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_config_file_rules() {
+    let dir = TempDir::new().unwrap();
+    dir.child("dify.toml")
+        .write_str(
+            r#"
+            [[rules]]
+            files = "**/tiger-2.jpg"
+            threshold = 1.0
+
+            [[rules]]
+            files = "**/yellow.jpg"
+            block-out = ["1,1,998,665"]
+            "#,
+        )
+        .unwrap();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap());
+    cmd.assert().success();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/yellow.jpg").unwrap())
+        .arg("--output")
+        .arg(dir.child("diff.png").path());
+    // only the first row and column are not blocked out
+    cmd.assert().code(130);
+
+    dir.close().unwrap();
+}
+
+// sends a request to the review server and returns the raw response
+fn request(port: u16, head: &str) -> String {
+    use std::io::{Read, Write};
+
+    let mut stream = std::net::TcpStream::connect(("127.0.0.1", port)).unwrap();
+    write!(
+        stream,
+        "{head}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn test_serve_approve() {
+    let dir = TempDir::new().unwrap();
+    for (which, fixture) in [("baseline", "tiger.jpg"), ("actual", "tiger-2.jpg")].iter() {
+        fs::create_dir(dir.child(which).path()).unwrap();
+        fs::copy(
+            format!("./benches/fixtures/{fixture}"),
+            dir.child(which).child("tiger.jpg").path(),
+        )
+        .unwrap();
+    }
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+
+    let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin("dify"))
+        .arg("serve")
+        .arg("--port")
+        .arg(port.to_string())
+        .arg("--dir")
+        .arg(dir.child("baseline").path())
+        .arg(dir.child("actual").path())
+        .stdout(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    while std::net::TcpStream::connect(("127.0.0.1", port)).is_err() {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+
+    let host = format!("Host: 127.0.0.1:{port}");
+    let page = request(port, &format!("GET / HTTP/1.1\r\n{host}"));
+    let token = page
+        .split("const token = \"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_owned();
+    let approve = |headers: &str| {
+        request(
+            port,
+            &format!("POST /approve/tiger.jpg HTTP/1.1\r\n{headers}"),
+        )
+    };
+
+    let cross_origin = approve(&format!(
+        "{host}\r\nOrigin: http://example.com\r\nX-Dify-Token: {token}"
+    ));
+    let rebound = approve(&format!(
+        "Host: example.com:{port}\r\nX-Dify-Token: {token}"
+    ));
+    let without_token = approve(&host);
+    let baseline_kept = fs::read(dir.child("baseline").child("tiger.jpg").path()).unwrap()
+        == fs::read("./benches/fixtures/tiger.jpg").unwrap();
+    let approved = approve(&format!(
+        "{host}\r\nOrigin: http://127.0.0.1:{port}\r\nX-Dify-Token: {token}"
+    ));
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert!(cross_origin.starts_with("HTTP/1.1 403"));
+    assert!(rebound.starts_with("HTTP/1.1 403"));
+    assert!(without_token.starts_with("HTTP/1.1 403"));
+    assert!(baseline_kept);
+    assert!(approved.starts_with("HTTP/1.1 200"));
+    dir.child("baseline")
+        .child("tiger.jpg")
+        .assert(predicate::path::eq_file(
+            fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap(),
+        ));
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_git_difftool() {
+    let dir = TempDir::new().unwrap();
+    let left = fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap();
+    let right = fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap();
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .arg("git-difftool")
+        .arg(&left)
+        .arg(&right)
+        .arg("screens/tiger.jpg")
+        .arg("--output")
+        .arg("diff.png");
+    cmd.assert()
+        .stdout(predicate::str::starts_with("\"screens/tiger.jpg\""));
+    dir.child("diff.png").assert(predicate::path::exists());
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.arg("git-difftool")
+        .arg("/dev/null")
+        .arg(&right)
+        .arg("screens/tiger.jpg");
+    cmd.assert()
+        .success()
+        .stdout("\"screens/tiger.jpg\" added\n");
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_textconv() {
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.arg("textconv")
+        .arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap());
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("dimensions: 1000x667\nhash: "));
+}
+
+#[test]
+fn test_stats() {
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.arg("stats")
+        .arg("./benches/fixtures/tiger.jpg")
+        .arg("./benches/fixtures/tiger-2.jpg");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::starts_with("red: chi-square "))
+        .stdout(predicate::str::contains("\ndominant colors:\n  #"));
+}
+
+#[test]
+fn test_color_profiles_srgb_and_untagged() {
+    // the left image has an sRGB profile and the right one none, which is taken to be sRGB
+    let temp = NamedTempFile::new("diff.png").unwrap();
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.arg("./benches/fixtures/tiger.jpg")
+        .arg("./benches/fixtures/tiger-2.jpg")
+        .arg("-o")
+        .arg(temp.path());
+    cmd.assert().stderr("");
+}
+
+#[test]
+fn test_animated() {
+    use image::{codecs::gif::GifEncoder, Delay, Frame, Rgba, RgbaImage};
+
+    let dir = TempDir::new().unwrap();
+    let write = |name: &str, colors: &[[u8; 4]], delay: u32| {
+        let file = fs::File::create(dir.child(name).path()).unwrap();
+        let mut encoder = GifEncoder::new(file);
+        for color in colors {
+            let image = RgbaImage::from_pixel(4, 4, Rgba(*color));
+            let delay = Delay::from_numer_denom_ms(delay, 1);
+            encoder
+                .encode_frame(Frame::from_parts(image, 0, 0, delay))
+                .unwrap();
+        }
+    };
+    write("left.gif", &[[0, 0, 0, 255], [255, 255, 255, 255]], 100);
+    write(
+        "right.gif",
+        &[[0, 0, 0, 255], [255, 0, 0, 255], [0, 0, 255, 255]],
+        100,
+    );
+    write("slow.gif", &[[0, 0, 0, 255], [255, 255, 255, 255]], 200);
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .env("NO_COLOR", "1")
+        .arg("left.gif")
+        .arg("right.gif")
+        .arg("--output")
+        .arg("diff.gif")
+        .arg("--mask-output")
+        .arg("mask.png");
+    cmd.assert()
+        .code(32)
+        .stderr("Warning: --mask-output skipped for animated images\n")
+        .stdout(
+            "frame counts are different, left: 2, right: 3\n\
+             frame 1: no differences\n\
+             frame 2: 16 different pixels\n\
+             frame 3: missing on one side\n",
+        );
+
+    let decoder = image::codecs::gif::GifDecoder::new(io::BufReader::new(
+        fs::File::open(dir.child("diff.gif").path()).unwrap(),
+    ))
+    .unwrap();
+    let frames = image::AnimationDecoder::into_frames(decoder)
+        .collect_frames()
+        .unwrap();
+    assert_eq!(3, frames.len());
+
+    // timing is reported without counting as different pixels
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .env("NO_COLOR", "1")
+        .arg("left.gif")
+        .arg("slow.gif")
+        .arg("--output")
+        .arg("diff.gif");
+    cmd.assert().success().stdout(
+        "frame 1: delays are different, left: 100ms, right: 200ms\n\
+         frame 1: no differences\n\
+         frame 2: delays are different, left: 100ms, right: 200ms\n\
+         frame 2: no differences\n",
+    );
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_sequence() {
+    use image::{Rgba, RgbaImage};
+
+    let dir = TempDir::new().unwrap();
+    let write = |name: &str, color: [u8; 4]| {
+        RgbaImage::from_pixel(4, 4, Rgba(color))
+            .save(dir.child(name).path())
+            .unwrap();
+    };
+    write("left_0001.png", [0, 0, 0, 255]);
+    write("left_0002.png", [0, 0, 0, 255]);
+    write("left_0003.png", [0, 0, 0, 255]);
+    write("right_0001.png", [0, 0, 0, 255]);
+    write("right_0002.png", [255, 255, 255, 255]);
+
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.current_dir(dir.path())
+        .env("NO_COLOR", "1")
+        .arg("--sequence")
+        .arg("left_%04d.png")
+        .arg("right_%04d.png")
+        .arg("--output")
+        .arg("diff_%04d.png");
+    cmd.assert().code(32).stdout(format!(
+        "frame counts are different, left: 3, right: 2\n\
+         frame 1: no differences\n\
+         frame 2: 16 different pixels {0}\n\
+         frame 3: missing on one side {0}\n\
+         first different frame: 2\n",
+        "#".repeat(40)
+    ));
+
+    dir.child("diff_0001.png").assert(predicate::path::exists());
+    dir.child("diff_0002.png").assert(predicate::path::exists());
+    dir.child("diff_0003.png").assert(predicate::path::exists());
+
+    dir.close().unwrap();
+}
+
+#[test]
+fn test_aa_budget() {
+    let output = NamedTempFile::new("diff.png").unwrap();
+    let mut cmd = Command::cargo_bin("dify").unwrap();
+    cmd.arg(fs::canonicalize("./benches/fixtures/tiger.jpg").unwrap())
+        .arg(fs::canonicalize("./benches/fixtures/tiger-2.jpg").unwrap())
+        .arg("--output")
+        .arg(output.path())
+        .arg("--aa-budget")
+        .arg("2");
+
+    cmd.assert()
+        .failure()
+        .stdout("4 anti-aliased pixels\n")
+        .stderr("Error: 4 anti-aliased pixels exceed the budget of 2\n");
+    output.assert(predicate::path::exists());
+}